@@ -1,109 +1,319 @@
 /// Amazons simulation and AI.
 pub mod solver;
+/// Multi-game network lobby.
+pub mod server;
+/// Terminal drawing of boards.
+pub mod render;
+/// Full-screen cursor-driven interface.
+pub mod tui;
 
 use solver::*;
 use solver::board::*;
+use solver::book::*;
+use solver::clock::*;
+use solver::endgame::*;
+use solver::notation::*;
+use solver::record::*;
+use solver::solve::*;
+use solver::tree::*;
 
 use std::io;
 use std::collections::HashMap;
 
-use termion::color;
+use render::*;
 
-fn parse_num(c: char) -> Option<i8> {
-    let c = c as u8;
-    if c >= b'1' && c <= b'8' {
-        Some((c - b'1' + 1) as i8)
-    } else if c >= b'a' && c <= b'h' {
-        Some((c - b'a' + 1) as i8)
-    } else if c >= b'A' && c <= b'H' {
-        Some((c - b'A' + 1) as i8)
-    } else {
-        None
-    }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Player {
+    Ai(EvalStrategy),
+    Human,
 }
 
-fn parse_pos(s: &str) -> Option<Pos> {
-    let mut chars = s.chars();
-    chars.next()
-        .and_then(parse_num)
-        .and_then(|row| chars.next()
-            .and_then(parse_num)
-            .map(|col| Pos { row, col }))
-        .filter(|_| chars.next().is_none())
+/// Moves to take back until a human is to move again: one in hotseat
+/// games, two against an AI.
+pub fn takeback_plies(amazons: &Amazons, players: &HashMap<Team, Player>) -> usize {
+    let ply = amazons.ply();
+    (1..=ply)
+        .find(|&k| players[&amazons.team_at(ply - k)] == Player::Human)
+        .unwrap_or(ply)
 }
 
-fn parse_move(s: &str) -> Option<(Pos,Pos,Pos)> {
-    let mut positions = s.split_ascii_whitespace()
-        .flat_map(parse_pos);
-    positions.next()
-        .and_then(|a| positions.next()
-            .and_then(|b| positions.next()
-                .map(|c| (a, b, c))))
-        .filter(|_| positions.next().is_none())
+/// Redo moves until a human is to move again, returning how many
+/// were replayed.
+pub fn redo_turn(amazons: &mut Amazons, players: &HashMap<Team, Player>) -> usize {
+    let mut redone = amazons.redo(1);
+    while redone > 0 && players[&amazons.to_move()] != Player::Human && amazons.redo(1) > 0 {
+        redone += 1;
+    }
+    redone
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-enum Player {
-    Ai(EvalStrategy),
-    Human,
-}
+/// `--analyze <file> [--depth <n>] [--csv <file>]`: print the first game
+/// of a record file annotated, with a graph of its scores.
+fn analyze_file() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+    let file = args.get(2).ok_or("Expected a record file to analyze")?;
+    let option = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    let depth = match option("--depth") {
+        Some(d) => d.parse().map_err(|_| format!("Invalid depth {:?}", d))?,
+        None => 3,
+    };
 
-fn render_board(amazons: &mut Amazons) {
-    let mut draw_board = DrawableBoard::new();
-    amazons.curr_board().draw_board(&mut draw_board);
+    let text = std::fs::read_to_string(file).map_err(|e| format!("Could not read {}: {}", file, e))?;
+    let record = parse_records(&text)?.into_iter().next().ok_or("No game in the file")?;
+    let analysis = analysis::analyze(&record, depth, EvalStrategy::QueenDistance)?;
 
-    println!();
-    for r in 0..draw_board.board.len() {
-        print!("  ");
-        for c in 0..draw_board.board[r].len() {
-            print!("{}", render_token(draw_board.board[r][c], (r+c)%2 == 0));
-            print!("{}", render_token(draw_board.board[r][c], (r+c)%2 == 0));
-        }
-        print!("{}", color::Bg(color::Reset));
-        println!();
+    println!("{}", analysis::annotate(&record, &analysis, depth));
+    print!("{}", analysis::eval_graph(&analysis));
+    if let Some(csv) = option("--csv") {
+        std::fs::write(csv, analysis::eval_csv(&analysis)).map_err(|e| format!("Could not write {}: {}", csv, e))?;
     }
-    print!("{}", color::Bg(color::Reset));
+    Ok(())
 }
 
-fn render_token(dt: DrawableToken, even: bool) -> String {
-    format!("{}{}{}", token_fg(dt), token_bg(dt, even), token_char(dt))
+/// `--build-book <file> [--size 8|10] [--plies n] [--width n] [--depth n]`:
+/// search the start of a standard board and write an opening book for it.
+fn build_book() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+    let file = args.get(2).ok_or("Expected a file to write the book to")?;
+    let option = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    let number = |name: &str, default: usize| match option(name) {
+        Some(n) => n.parse().map_err(|_| format!("Invalid {} {:?}", &name[2..], n)),
+        None => Ok(default),
+    };
+    let amazons = match option("--size").map_or("8", |s| s.as_str()) {
+        "5" => Amazons::new_5x5(),
+        "8" => Amazons::new_8x8(),
+        "10" => Amazons::new_10x10(),
+        size => return Err(format!("No standard start for size {:?}", size)),
+    };
+    let (plies, width, depth) = (number("--plies", 4)?, number("--width", 3)?, number("--depth", 3)?);
+
+    let mut searched = 0;
+    let book = OpeningBook::build(amazons.curr_board(), amazons.to_move(), plies, width, depth as i32,
+                                  EvalStrategy::QueenDistance, &mut |board, team| {
+        searched += 1;
+        println!("{:>4}. {}", searched, board.to_fen(team));
+    });
+    let text = format!("// Built with --plies {} --width {} --depth {}\n{}", plies, width, depth, book);
+    std::fs::write(file, text).map_err(|e| format!("Could not write {}: {}", file, e))?;
+    println!("Wrote {} positions to {}", book.len(), file);
+    Ok(())
 }
 
-fn token_char(dt: DrawableToken) -> String {
-    match dt {
-        DrawableToken::Empty => String::from(" "),
-        DrawableToken::Wall => String::from("#"),
-        DrawableToken::Piece(Team::Red) => String::from("R"),
-        DrawableToken::Piece(Team::Blue) => String::from("B"),
-    }
+/// `--build-regions <file> [--games n] [--width n]`: play games from
+/// each standard start and write a database of the regions met.
+fn build_regions() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+    let file = args.get(2).ok_or("Expected a file to write the database to")?;
+    let option = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    let number = |name: &str, default: usize| match option(name) {
+        Some(n) => n.parse().map_err(|_| format!("Invalid {} {:?}", &name[2..], n)),
+        None => Ok(default),
+    };
+    let (games, width) = (number("--games", 50)?, number("--width", 3)?);
+
+    let mut played = 0;
+    let database = RegionDatabase::build(games, width, &mut |board, shapes| {
+        played += 1;
+        println!("{:>4}. {1}x{1} game, {2} shapes", played, board.board_size() - 2, shapes);
+    });
+    let text = format!("// Built with --games {} --width {}\n{}", games, width, database);
+    std::fs::write(file, text).map_err(|e| format!("Could not write {}: {}", file, e))?;
+    println!("Wrote {} shapes to {}", database.len(), file);
+    Ok(())
 }
 
-fn token_fg(dt: DrawableToken) -> String {
-    match dt {
-        DrawableToken::Empty => format!("{}", color::Fg(color::LightBlack)),
-        DrawableToken::Wall => format!("{}", color::Fg(color::White)),
-        DrawableToken::Piece(Team::Red) => format!("{}", color::Fg(color::Rgb(250, 60, 60))),
-        DrawableToken::Piece(Team::Blue) => format!("{}", color::Fg(color::Rgb(32, 155, 250))),
+/// `--solve [--fen <position>] [--out <file>] [--cache-bits n]`: find
+/// who wins the 5x5 start, or another small position, with perfect play,
+/// and save every position solved on the way.
+fn solve_position() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+    let option = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    let amazons = match option("--fen") {
+        Some(fen) => Amazons::from_fen(fen)?,
+        None => Amazons::new_5x5(),
+    };
+    let (board, team) = (amazons.curr_board(), amazons.to_move());
+    let bits = match option("--cache-bits") {
+        Some(b) => b.parse().ok().filter(|b| (10..=32).contains(b)).ok_or(format!("Invalid cache bits {:?}", b))?,
+        None => SOLVER_CACHE_BITS + 4,
+    };
+
+    println!("Solving {} with {} MB of caches", amazons.to_fen(), SOLVER_SLOT_BYTES << bits >> 20);
+    let mut solver = Solver::with_cache_bits(bits);
+    let start = std::time::Instant::now();
+    let (sender, progress) = std::sync::mpsc::channel();
+    solver.report_to(sender);
+    std::thread::spawn(move || {
+        for p in progress {
+            println!("    {} positions, {} in the database, {:.0}s", p.nodes, p.solved, start.elapsed().as_secs_f64());
+        }
+    });
+
+    // Solve the moves one at a time, likeliest first, to show how far
+    // along the search is and to stop at the first that wins.
+    let mut regions = RegionTable::new();
+    let mut seen = std::collections::HashSet::new();
+    let mut moves: Vec<Board> = board.successors(team)
+        .filter(|b| seen.insert(b.canonical_key().0))
+        .collect();
+    moves.sort_by_cached_key(|b| std::cmp::Reverse(b.evaluate(team, EvalStrategy::QueenDistance, &mut regions)));
+    let mut winning = None;
+    for (i, next) in moves.iter().enumerate() {
+        let wins = !solver.solve(next, team.other())?;
+        println!("  move {}/{} {} {} ({:.0}s)", i + 1, moves.len(), board.diff_move(next).unwrap(),
+                 if wins { "wins" } else { "loses" }, start.elapsed().as_secs_f64());
+        if wins {
+            winning = Some(next);
+            break;
+        }
+    }
+    // Quick with the moves solved, and puts the start in the database.
+    let win = solver.solve(board, team)?;
+    println!("{:?} to move {} {} ({} positions in {:.1}s)", team, if win { "wins" } else { "loses" },
+             amazons.to_fen(), solver.nodes, start.elapsed().as_secs_f64());
+    match winning {
+        Some(next) => println!("A winning move: {}", board.diff_move(next).unwrap()),
+        None => println!("Every move loses against best play"),
+    }
+    if let Some(out) = option("--out") {
+        let db = solver.database();
+        std::fs::write(out, db.to_bytes()).map_err(|e| format!("Could not write {}: {}", out, e))?;
+        println!("Wrote {} positions to {}", db.len(), out);
     }
+    Ok(())
 }
 
-fn token_bg(dt: DrawableToken, even: bool) -> String {
-    let checkered = if even {
-        format!("{}", color::Bg(color::Rgb(128, 76, 21)))
-    } else {
-        format!("{}", color::Bg(color::Rgb(140, 90, 40)))
+/// `--bench [--moves n]`: time move generation and evaluation on
+/// positions from greedy games from the 8x8 and 10x10 starts, then the
+/// first `n` moves of an AI game from each without a book, searched as
+/// `ai_move` does with its transposition table.
+fn benchmark() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+    let option = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    let moves = match option("--moves") {
+        Some(n) => n.parse().map_err(|_| format!("Invalid moves {:?}", n))?,
+        None => 10,
     };
+    let strategy = EvalStrategy::QueenDistance;
+    // Repeat each timing for at least this long.
+    let least = std::time::Duration::from_millis(200);
+    let per_second = |count: u64, time: std::time::Duration| count as f64 / time.as_secs_f64().max(1e-9);
+
+    println!("{:>5} {:>4} {:>6} {:>12} {:>12}", "board", "ply", "moves", "boards/s", "evals/s");
+    let mut regions = RegionTable::new();
+    for start in [Amazons::new_8x8(), Amazons::new_10x10()] {
+        let (mut board, mut team) = (start.curr_board().clone(), start.to_move());
+        for ply in 0..=30 {
+            if ply % 10 == 0 {
+                let timer = std::time::Instant::now();
+                let mut generated = 0;
+                while generated == 0 || timer.elapsed() < least {
+                    generated += board.successors(team).count() as u64;
+                }
+                let generating = timer.elapsed();
 
-    match dt {
-        DrawableToken::Wall => checkered,//format!("{}", color::Bg(color::Rgb(30, 30, 30))),
-        DrawableToken::Empty => checkered,
-        DrawableToken::Piece(_) => checkered,
+                let after: Vec<Board> = board.successors(team).collect();
+                let timer = std::time::Instant::now();
+                let mut evaluated = 0;
+                while evaluated == 0 || timer.elapsed() < least {
+                    after.iter().for_each(|b| { std::hint::black_box(b.evaluate(team, strategy, &mut regions)); });
+                    evaluated += after.len().max(1) as u64;
+                }
+                let evaluating = timer.elapsed();
+                println!("{:>5} {:>4} {:>6} {:>12.0} {:>12.0}", format!("{0}x{0}", board.board_size() - 2),
+                         ply, after.len(), per_second(generated, generating), per_second(evaluated, evaluating));
+            }
+            board = match board.successors(team).max_by_key(|b| b.evaluate(team, strategy, &mut regions)) {
+                Some(next) => next,
+                None => break,
+            };
+            team = team.other();
+        }
     }
+
+    println!();
+    println!("{:>5} {:>6} {:>12} {:>10} {:>12} {:>15}", "board", "moves", "nodes", "hits", "s/move", "search nodes/s");
+    let (mut nodes, mut searching) = (0, std::time::Duration::ZERO);
+    for mut amazons in [Amazons::new_8x8(), Amazons::new_10x10()] {
+        amazons.set_book(None);
+        let (mut played, mut game_nodes, mut hits) = (0, 0, 0);
+        let timer = std::time::Instant::now();
+        while played < moves {
+            match amazons.ai_move(amazons.to_move(), strategy) {
+                Some(info) => {
+                    game_nodes += info.nodes;
+                    hits += info.table_hits;
+                },
+                None => break,
+            }
+            played += 1;
+        }
+        let elapsed = timer.elapsed();
+        nodes += game_nodes;
+        searching += elapsed;
+        println!("{:>5} {:>6} {:>12} {:>10} {:>12.2} {:>15.0}", format!("{0}x{0}", amazons.curr_board().board_size() - 2),
+                 played, game_nodes, hits, elapsed.as_secs_f64() / played.max(1) as f64, per_second(game_nodes, elapsed));
+    }
+    println!("Searched {} nodes in {:.2}s ({:.0} nodes/s)", nodes, searching.as_secs_f64(), per_second(nodes, searching));
+    Ok(())
 }
 
 fn main() {
     let mut input: HashMap<Team, Player> = HashMap::new();
 
+    if std::env::args().nth(1) == Some(String::from("--server")) {
+        let addr = std::env::args().nth(2).unwrap_or_else(|| String::from("127.0.0.1:7878"));
+        let dir = std::env::args().nth(3).unwrap_or_else(|| String::from("lobby"));
+        if let Err(e) = server::serve(&addr, std::path::Path::new(&dir)) {
+            println!("Server stopped: {}", e);
+        }
+        return;
+    }
+
+    if std::env::args().nth(1) == Some(String::from("--solve")) {
+        if let Err(msg) = solve_position() {
+            println!("{}", msg);
+        }
+        return;
+    }
+
+    if std::env::args().nth(1) == Some(String::from("--build-book")) {
+        if let Err(msg) = build_book() {
+            println!("{}", msg);
+        }
+        return;
+    }
+
+    if std::env::args().nth(1) == Some(String::from("--build-regions")) {
+        if let Err(msg) = build_regions() {
+            println!("{}", msg);
+        }
+        return;
+    }
+
+    if std::env::args().nth(1) == Some(String::from("--bench")) {
+        if let Err(msg) = benchmark() {
+            println!("{}", msg);
+        }
+        return;
+    }
+
+    if std::env::args().nth(1) == Some(String::from("--analyze")) {
+        if let Err(msg) = analyze_file() {
+            println!("{}", msg);
+        }
+        return;
+    }
+
+    let style = match Style::from_env() {
+        Ok(style) => style,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+
     if std::env::args().nth(1) == Some(String::from("--ai-battle")) {
         for t in Team::teams() {
             input.insert(t, Player::Ai(EvalStrategy::QueenDistance));
@@ -132,51 +342,250 @@ fn main() {
         }
     }
 
-    let mut amazons = Amazons::new_8x8();
-    let mut team = Team::Red;
+    let args: Vec<String> = std::env::args().collect();
+    let mut amazons = match args.iter().position(|a| a == "--fen") {
+        Some(i) => match Amazons::from_fen(args.get(i + 1).map(|s| s.as_str()).unwrap_or("")) {
+            Ok(amazons) => amazons,
+            Err(msg) => {
+                println!("Invalid position: {}", msg);
+                return;
+            }
+        },
+        None => Amazons::new_8x8(),
+    };
+    // Saving a loaded game keeps the tags it was loaded with.
+    let mut loaded_record = None;
+    if let Some(i) = args.iter().position(|a| a == "--load") {
+        let loaded = args.get(i + 1)
+            .ok_or(String::from("missing file name"))
+            .and_then(|f| std::fs::read_to_string(f).map_err(|e| e.to_string()))
+            .and_then(|text| parse_records(&text))
+            .and_then(|records| records.into_iter().next().ok_or(String::from("no games in file")))
+            .and_then(|record| record.replay().map(|game| (game, record)));
+        match loaded {
+            Ok((game, record)) => {
+                amazons = game;
+                loaded_record = Some(record);
+            },
+            Err(msg) => {
+                println!("Could not load game: {}", msg);
+                return;
+            }
+        }
+    }
+    if let Some(i) = args.iter().position(|a| a == "--diagram" || a == "--old-diagram") {
+        let diagram = match args.get(i + 1).map(std::fs::read_to_string) {
+            Some(Ok(diagram)) => diagram,
+            _ => {
+                println!("Could not read the diagram file");
+                return;
+            }
+        };
+        let read = match args[i].as_str() {
+            "--old-diagram" => Board::from_old_diagram(&diagram),
+            _ => Board::from_diagram(&diagram),
+        };
+        match read {
+            Ok(board) => {
+                amazons = Amazons::from_board(board, Team::Red);
+                loaded_record = None;
+            },
+            Err(msg) => {
+                println!("Invalid diagram: {}", msg);
+                return;
+            }
+        }
+    }
+
+    if let Some(i) = args.iter().position(|a| a == "--clock") {
+        match TimeControl::parse(args.get(i + 1).map(|s| s.as_str()).unwrap_or("")) {
+            Ok(control) => amazons.set_clock(control),
+            Err(msg) => {
+                println!("Invalid time control: {}", msg);
+                return;
+            }
+        }
+    }
+
+    if args.iter().any(|a| a == "--no-book") {
+        amazons.set_book(None);
+    } else if let Some(i) = args.iter().position(|a| a == "--book") {
+        let book = args.get(i + 1)
+            .ok_or(String::from("missing file name"))
+            .and_then(|f| std::fs::read_to_string(f).map_err(|e| e.to_string()))
+            .and_then(|text| OpeningBook::parse(&text));
+        match book {
+            Ok(book) => amazons.set_book(Some(book)),
+            Err(msg) => {
+                println!("Could not load opening book: {}", msg);
+                return;
+            }
+        }
+    }
+
+    if let Some(i) = args.iter().position(|a| a == "--solved") {
+        let solved = args.get(i + 1)
+            .ok_or(String::from("missing file name"))
+            .and_then(|f| std::fs::read(f).map_err(|e| e.to_string()))
+            .and_then(|bytes| SolvedPositions::from_bytes(&bytes));
+        match solved {
+            Ok(solved) => amazons.set_solved(Some(solved)),
+            Err(msg) => {
+                println!("Could not load solved positions: {}", msg);
+                return;
+            }
+        }
+    }
+
+    // Let an AI think on its opponent's time, starting with the reply
+    // it expects.
+    let ponder = args.iter().any(|a| a == "--ponder");
+
+    if args.iter().any(|a| a == "--tui") {
+        if let Err(e) = tui::run(amazons, &input, style, ponder) {
+            println!("Terminal error: {}", e);
+        }
+        return;
+    }
+
+    // Show the territory the AI counts, if there is one.
+    let territory_strategy = input.values()
+        .find_map(|p| match p {
+            Player::Ai(s) => Some(*s),
+            Player::Human => None,
+        })
+        .unwrap_or(EvalStrategy::QueenDistance);
+    let mut show_territory = false;
+    let players = &input;
+    // Print what each AI search did.
+    let verbose = args.iter().any(|a| a == "--verbose" || a == "-v");
+    let mut predicted: Option<Move> = None;
 
     loop {
+        let team = amazons.to_move();
         let player = input[&team];
-        render_board(&mut amazons);
+        render_board(&mut amazons, Some(territory_strategy).filter(|_| show_territory), style);
         println!();
+        if let Some(winner) = amazons.winner() {
+            match amazons.clock().and_then(|c| c.flagged()) {
+                Some(flagged) => println!("{:?} ran out of time, {:?} wins", flagged, winner),
+                None => println!("{:?} has no moves left, {:?} wins", winner.other(), winner),
+            }
+            break;
+        }
         println!("{:?} to pick a move, controlled by {:?}", team, player);
 
         match player {
             Player::Ai(s) => {
-                if amazons.ai_move(team, s) {
+                if let Some(info) = amazons.ai_move(team, s) {
                     println!("Ai evaluation went from {} to {}", amazons.evaluate(1, team, s), amazons.evaluate(0, team, s));
-                    team = team.other();
+                    if verbose {
+                        println!("{}", info);
+                    }
+                    predicted = info.pv.get(1).cloned();
                 } else {
                     println!("AI for team {:?} gives up", team);
                     break;
                 }
             },
             Player::Human => {
+                let pondering = match input[&team.other()] {
+                    Player::Ai(s) if ponder => Some(amazons.start_ponder(team.other(), s, predicted.take())),
+                    _ => None,
+                };
                 let mut buffer = String::new();
                 loop {
-                    println!("Choose move for team {:?} in format 'RowCol RowCol RowCol'", team);
+                    println!("Choose move for team {:?} as 'from-to/shot', e.g. 'c3-c5/e5'", team);
                     buffer.clear();
                     io::stdin().read_line(&mut buffer)
                         .expect("failed to read line");
                     let input = buffer.trim();
 
                     if input == "ai" {
-                        amazons.ai_move(team, EvalStrategy::QueenDistance);
-                        team = team.other();
+                        match amazons.ai_move(team, EvalStrategy::QueenDistance) {
+                            Some(info) if verbose => println!("{}", info),
+                            Some(_) => {},
+                            None => println!("AI has no move for team {:?}", team),
+                        }
                         break;
+                    } else if let Some(args) = command_args(input, "hint") {
+                        let mut n = 3;
+                        let mut on_board = false;
+                        for arg in args.split_ascii_whitespace() {
+                            match arg.parse() {
+                                Ok(count) => n = count,
+                                Err(_) => on_board = arg == "board",
+                            }
+                        }
+                        let hints = amazons.hints(n, territory_strategy);
+                        render_hints(&amazons, &hints, on_board, style);
                     } else if input == "pieces" {
                         println!("Team {:?} has the following pieces:", team);
                         for p in amazons.team_pieces(team) {
-                            println!("    {}{}", p.row, p.col);
+                            println!("    {}", p);
                         }
                         println!();
-                    } else if input == "undo" {
-                        amazons.undo_2_move();
+                    } else if let Some(n) = command_args(input, "undo") {
+                        let n = match count_arg(n) {
+                            Ok(Some(n)) => n,
+                            Ok(None) => takeback_plies(&amazons, players),
+                            Err(msg) => {
+                                println!("{}", msg);
+                                continue;
+                            },
+                        };
+                        match amazons.undo(n) {
+                            0 => println!("Nothing to undo"),
+                            undone => {
+                                println!("Took back {} moves", undone);
+                                break;
+                            },
+                        }
+                    } else if let Some(n) = command_args(input, "redo") {
+                        let redone = match count_arg(n) {
+                            Ok(Some(n)) => amazons.redo(n),
+                            Ok(None) => redo_turn(&mut amazons, players),
+                            Err(msg) => {
+                                println!("{}", msg);
+                                continue;
+                            },
+                        };
+                        match redone {
+                            0 => println!("Nothing to redo"),
+                            redone => {
+                                println!("Replayed {} moves", redone);
+                                break;
+                            },
+                        }
+                    } else if let Some(file) = command_args(input, "save") {
+                        if file.is_empty() {
+                            println!("Expected a file to save to");
+                            continue;
+                        }
+                        let record = match &loaded_record {
+                            Some(loaded) => loaded.updated(&amazons),
+                            None => GameRecord::from_game(&amazons),
+                        };
+                        let record = record.to_string();
+                        match std::fs::write(file, record) {
+                            Ok(()) => println!("Saved to {}", file),
+                            Err(e) => println!("Could not save: {}", e),
+                        }
+                    } else if input == "fen" {
+                        println!("{}", amazons.to_fen());
+                    } else if input == "value" {
+                        render_values(amazons.curr_board(), team);
+                    } else if input == "territory" {
+                        show_territory = !show_territory;
                         break;
+                    } else if tree_info(amazons.tree(), input) {
+                    } else if let Some(moved) = amazons.navigate(|tree| tree_command(tree, input)) {
+                        if moved {
+                            break;
+                        }
                     } else if let Some((p,m,s)) = parse_move(input) {
                         match amazons.player_move(team, p, m, s) {
                             Ok(()) => {
-                                team = team.other();
                                 break;
                             },
                             Err(msg) => {
@@ -187,7 +596,178 @@ fn main() {
                         println!("Could not parse coords");
                     }
                 }
+                if let Some(handle) = pondering {
+                    let info = handle.cancel();
+                    if verbose {
+                        println!("Pondered: {}", info);
+                    }
+                }
             }
         }
     }
 }
+
+/// The rest of `input` if its first word is `command`.
+fn command_args<'a>(input: &'a str, command: &str) -> Option<&'a str> {
+    let rest = input.strip_prefix(command)?;
+    match rest.chars().next() {
+        None => Some(rest),
+        Some(c) if c.is_whitespace() => Some(rest.trim()),
+        Some(_) => None,
+    }
+}
+
+/// The number of moves after a command such as `undo 3`, or None if
+/// there is nothing after it.
+fn count_arg(args: &str) -> Result<Option<usize>, String> {
+    match args {
+        "" => Ok(None),
+        n => n.parse().map(Some).map_err(|_| format!("Expected a number of moves, not {:?}", n)),
+    }
+}
+
+/// Show what the game tree holds. Returns whether `input` was such a
+/// command.
+fn tree_info(tree: &GameTree, input: &str) -> bool {
+    match input.split_ascii_whitespace().next() {
+        Some("variations") => {
+            let moves = tree.continuations();
+            if moves.is_empty() {
+                println!("No moves played from here yet");
+            }
+            for (i, mv) in moves.iter().enumerate() {
+                println!("    {}: {}{}", i, mv, if i == 0 { " (main line)" } else { "" });
+            }
+            true
+        },
+        Some("branches") => {
+            for (name, ply) in tree.names() {
+                println!("    {} (ply {})", name, ply);
+            }
+            true
+        },
+        _ => false,
+    }
+}
+
+/// Move around the game tree. Returns None if `input` is not a tree
+/// command, otherwise whether the cursor moved.
+fn tree_command(tree: &mut GameTree, input: &str) -> Option<bool> {
+    let mut words = input.split_ascii_whitespace();
+    let command = words.next()?;
+    let arg = words.next();
+    let count = || arg.map_or(Some(1), |a| a.parse().ok());
+
+    match command {
+        "back" | "forward" => {
+            let n = match count() {
+                Some(n) => n,
+                None => {
+                    println!("Expected a number of moves");
+                    return Some(false);
+                }
+            };
+            let taken = match command {
+                "back" => tree.back(n),
+                _ => tree.forward(n),
+            };
+            if taken < n {
+                println!("Only {} moves to go {}", taken, command);
+            }
+            Some(taken > 0)
+        },
+        "jump" => match arg.and_then(|a| a.parse().ok()) {
+            Some(ply) if tree.jump(ply) => Some(true),
+            Some(ply) => {
+                println!("The line does not reach ply {}", ply);
+                Some(false)
+            },
+            None => {
+                println!("Expected a ply to jump to");
+                Some(false)
+            },
+        },
+        "variation" => match arg.and_then(|a| a.parse().ok()) {
+            Some(i) if tree.enter(i) => Some(true),
+            _ => {
+                println!("No such variation, see 'variations'");
+                Some(false)
+            },
+        },
+        "promote" => {
+            tree.promote();
+            println!("This line is now the main line");
+            Some(false)
+        },
+        "branch" => match arg {
+            Some(name) => {
+                tree.set_name(name);
+                println!("Named this position {:?}", name);
+                Some(false)
+            },
+            None => {
+                println!("Expected a name for the branch");
+                Some(false)
+            },
+        },
+        "goto" => match arg {
+            Some(name) if tree.goto(name) => Some(true),
+            _ => {
+                println!("No branch named {:?}, see 'branches'", arg.unwrap_or(""));
+                Some(false)
+            },
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 5x5 game two moves in, with both taken back.
+    fn undone_game() -> Amazons {
+        let mut amazons = Amazons::new_5x5();
+        for _ in 0..2 {
+            let team = amazons.to_move();
+            let board = amazons.curr_board().clone();
+            let mv = board.successors(team).next().and_then(|b| board.diff_move(&b)).unwrap();
+            amazons.player_move(team, mv.player.pos, mv.new_pos, mv.new_shot).unwrap();
+        }
+        assert_eq!(amazons.undo(2), 2);
+        amazons
+    }
+
+    #[test]
+    fn commands_match_whole_words() {
+        assert_eq!(command_args("undo", "undo"), Some(""));
+        assert_eq!(command_args("undo 3", "undo"), Some("3"));
+        assert_eq!(command_args("undone", "undo"), None);
+        assert_eq!(command_args("saveas x", "save"), None);
+        assert_eq!(command_args("save  game.txt ", "save"), Some("game.txt"));
+        assert_eq!(count_arg(""), Ok(None));
+        assert_eq!(count_arg("3"), Ok(Some(3)));
+        assert!(count_arg("abc").is_err());
+        assert!(count_arg("-1").is_err());
+    }
+
+    #[test]
+    fn redo_survives_commands_that_do_not_move() {
+        let mut amazons = undone_game();
+        for input in ["variations", "branches"].iter() {
+            assert!(tree_info(amazons.tree(), input));
+        }
+        for input in ["a1-z9/b2", "variation 5", "back", "goto nowhere", "branch here", "promote"].iter() {
+            amazons.navigate(|tree| tree_command(tree, input));
+        }
+        assert_eq!(amazons.navigate(|tree| tree_command(tree, "jump 9")), Some(false));
+        assert_eq!(amazons.redo(2), 2);
+    }
+
+    #[test]
+    fn moving_the_cursor_clears_redo() {
+        let mut amazons = undone_game();
+        assert_eq!(amazons.navigate(|tree| tree_command(tree, "forward")), Some(true));
+        assert_eq!(amazons.redo(1), 0);
+    }
+}
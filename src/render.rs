@@ -0,0 +1,427 @@
+use crate::solver::*;
+use crate::solver::board::*;
+use crate::solver::cgt::*;
+use crate::solver::clock::*;
+
+use std::io;
+
+use termion::color;
+
+/// Extra emphasis for a square, drawn instead of the checkered background.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Highlight {
+    Cursor,
+    Selected,
+    Reachable,
+    Target,
+    LastFrom,
+    LastTo,
+    LastShot,
+    Territory(Ownership),
+}
+
+type Rgb = (u8, u8, u8);
+
+/// Colors used by the colored renderer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Theme {
+    pub red: Rgb,
+    pub blue: Rgb,
+    pub even: Rgb,
+    pub odd: Rgb,
+    pub cursor: Rgb,
+    pub selected: Rgb,
+    pub reachable: Rgb,
+    pub target: Rgb,
+    pub last_from: Rgb,
+    pub last_to: Rgb,
+    pub last_shot: Rgb,
+    pub red_area: Rgb,
+    pub blue_area: Rgb,
+    pub tied_area: Rgb,
+    pub unreachable_area: Rgb,
+}
+
+pub const CLASSIC: Theme = Theme {
+    red: (250, 60, 60),
+    blue: (32, 155, 250),
+    even: (128, 76, 21),
+    odd: (140, 90, 40),
+    cursor: (200, 180, 40),
+    selected: (40, 150, 60),
+    reachable: (100, 130, 50),
+    target: (150, 60, 40),
+    last_from: (90, 90, 90),
+    last_to: (160, 160, 60),
+    last_shot: (120, 40, 120),
+    red_area: (130, 50, 45),
+    blue_area: (45, 75, 130),
+    tied_area: (100, 75, 100),
+    unreachable_area: (55, 55, 55),
+};
+
+/// Orange and sky blue instead of red and blue, from the Okabe-Ito
+/// palette, which stays distinct under the common color blindnesses.
+pub const COLORBLIND: Theme = Theme {
+    red: (230, 159, 0),
+    blue: (86, 180, 233),
+    even: (70, 70, 70),
+    odd: (85, 85, 85),
+    cursor: (240, 228, 66),
+    selected: (0, 158, 115),
+    reachable: (0, 110, 80),
+    target: (204, 121, 167),
+    last_from: (120, 120, 120),
+    last_to: (150, 140, 40),
+    last_shot: (120, 70, 100),
+    red_area: (125, 85, 10),
+    blue_area: (35, 90, 125),
+    tied_area: (105, 105, 105),
+    unreachable_area: (35, 35, 35),
+};
+
+pub const SLATE: Theme = Theme {
+    red: (255, 110, 90),
+    blue: (120, 200, 255),
+    even: (50, 60, 75),
+    odd: (62, 72, 88),
+    cursor: (200, 180, 40),
+    selected: (40, 150, 60),
+    reachable: (60, 110, 70),
+    target: (140, 60, 60),
+    last_from: (100, 100, 110),
+    last_to: (150, 150, 70),
+    last_shot: (110, 60, 130),
+    red_area: (110, 55, 60),
+    blue_area: (40, 80, 120),
+    tied_area: (85, 75, 100),
+    unreachable_area: (30, 30, 35),
+};
+
+pub fn theme_named(name: &str) -> Option<Theme> {
+    match name {
+        "classic" => Some(CLASSIC),
+        "colorblind" => Some(COLORBLIND),
+        "slate" => Some(SLATE),
+        _ => None,
+    }
+}
+
+/// How boards are drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    Colored(Theme),
+    /// Plain characters without escape codes, for logs and dumb terminals.
+    Plain,
+}
+
+impl Style {
+    /// Pick a style from the command line and environment.
+    ///
+    /// `--plain` or a non-empty `NO_COLOR` turn colors off, as does
+    /// stdout not being a terminal. `--theme <name>` picks the colors.
+    pub fn from_env() -> Result<Style, String> {
+        let args: Vec<String> = std::env::args().collect();
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        if no_color || args.iter().any(|a| a == "--plain") || !termion::is_tty(&io::stdout()) {
+            return Ok(Style::Plain);
+        }
+        match args.iter().position(|a| a == "--theme") {
+            Some(i) => {
+                let name = args.get(i + 1).map(|s| s.as_str()).unwrap_or("");
+                theme_named(name)
+                    .map(Style::Colored)
+                    .ok_or(format!("Unknown theme {:?}, try classic, colorblind or slate", name))
+            },
+            None => Ok(Style::Colored(CLASSIC)),
+        }
+    }
+}
+
+/// Characters taken by the row labels at the start of each board line.
+pub const LABEL_WIDTH: u16 = 3;
+
+/// Print the current board, tinted by territory if a heuristic is given,
+/// and the clocks if the game is timed.
+pub fn render_board(amazons: &mut Amazons, territory: Option<EvalStrategy>, style: Style) {
+    let last = amazons.last_move();
+    let owners = territory.map(|s| amazons.territory(s));
+    let highlight = |p| last_move_highlight(&last, p)
+        .or_else(|| owners.as_ref().and_then(|t| t.owner(p)).map(Highlight::Territory));
+    println!();
+    for line in board_lines(amazons.curr_board(), &highlight, style) {
+        println!("  {}", line);
+    }
+    if let Some(t) = owners {
+        println!("  {}", territory_summary(&t));
+        println!("  {}", regions_summary(amazons.curr_board()));
+    }
+    if let Some(clock) = amazons.clock() {
+        println!("  {}", clock_summary(clock));
+    }
+}
+
+/// Both teams' time, with the running one marked.
+pub fn clock_summary(clock: &Clock) -> String {
+    let times: Vec<String> = Team::teams().into_iter()
+        .map(|t| {
+            let mark = if clock.running() == Some(t) { "*" } else { "" };
+            format!("{:?}{} {}", t, mark, clock.describe(t))
+        })
+        .collect();
+    format!("Clock ({}): {}", clock.control(), times.join("  "))
+}
+
+/// Square counts for each kind of owner.
+pub fn territory_summary(t: &Territory) -> String {
+    format!("Territory: Red {}, Blue {}, tied {}, unreachable {}",
+            t.count(Ownership::Owned(Team::Red)), t.count(Ownership::Owned(Team::Blue)),
+            t.count(Ownership::Tied), t.count(Ownership::Unreachable))
+}
+
+/// How the empty squares split into regions, and how many squares lie
+/// in regions only one team can reach.
+pub fn regions_summary(board: &Board) -> String {
+    let regions = board.regions();
+    let contested = regions.iter().filter(|r| r.is_contested()).count();
+    let alone = |team: Team| -> usize {
+        regions.iter()
+            .filter(|r| !r.is_contested() && !r.pieces(team).is_empty())
+            .map(|r| r.squares.len())
+            .sum()
+    };
+    format!("Regions: {} ({} contested), Red alone {}, Blue alone {}",
+            regions.len(), contested, alone(Team::Red), alone(Team::Blue))
+}
+
+/// One line describing a hint: its rank, move, score and territory.
+pub fn hint_summary(rank: usize, hint: &Hint) -> String {
+    format!("{}. {} (score {}) {}", rank, hint.mv, hint.score, territory_summary(&hint.territory))
+}
+
+/// Mark where hinted moves start, go to and shoot.
+pub fn hint_highlight(hints: &[Hint], p: Pos) -> Option<Highlight> {
+    if hints.iter().any(|h| h.mv.player.pos == p) {
+        Some(Highlight::Selected)
+    } else if hints.iter().any(|h| h.mv.new_pos == p) {
+        Some(Highlight::Reachable)
+    } else if hints.iter().any(|h| h.mv.new_shot == p) {
+        Some(Highlight::Target)
+    } else {
+        None
+    }
+}
+
+/// Print the hints, and the board with them marked if `on_board`.
+pub fn render_hints(amazons: &Amazons, hints: &[Hint], on_board: bool, style: Style) {
+    if hints.is_empty() {
+        println!("No moves to suggest");
+        return;
+    }
+    for (i, hint) in hints.iter().enumerate() {
+        println!("  {}", hint_summary(i + 1, hint));
+    }
+    if on_board {
+        println!();
+        for line in board_lines(amazons.curr_board(), &|p| hint_highlight(hints, p), style) {
+            println!("  {}", line);
+        }
+    }
+}
+
+/// Print the game value of each region of the board, and who wins
+/// the sum with `team` to move.
+pub fn render_values(board: &Board, team: Team) {
+    let mut values = RegionValues::new();
+    let components = match values.components(board) {
+        Some(components) => components,
+        None => {
+            println!("Some regions are too big to work out");
+            return;
+        },
+    };
+    for (component, value) in &components {
+        let corner = component.squares.iter().chain(component.pieces.iter().map(|p| &p.pos))
+            .min_by_key(|p| (p.row, p.col))
+            .unwrap();
+        println!("  region at {} ({} squares): {}", corner, component.squares.len(), value_name(values.games(), *value));
+    }
+    let total = values.value(board).unwrap();
+    let total = value_name(values.games(), total);
+    match values.winning_move(board, team) {
+        Some(next) => println!("Sum {}: {:?} wins with {}", total, team, board.diff_move(&next).unwrap()),
+        None => println!("Sum {}: {:?} loses against best play", total, team),
+    }
+}
+
+/// The name of a game value, unless it is too long to read.
+fn value_name(games: &Games, g: Game) -> String {
+    let name = games.name(g);
+    match name.chars().count() {
+        0..=60 => name,
+        _ => String::from("a game too long to write out"),
+    }
+}
+
+/// Mark the squares touched by the previous move.
+pub fn last_move_highlight(last: &Option<Move>, p: Pos) -> Option<Highlight> {
+    match last {
+        Some(mv) if mv.new_shot == p => Some(Highlight::LastShot),
+        Some(mv) if mv.new_pos == p => Some(Highlight::LastTo),
+        Some(mv) if mv.player.pos == p => Some(Highlight::LastFrom),
+        _ => None,
+    }
+}
+
+/// Board drawing without the border walls, two characters per square.
+///
+/// The first line labels the files, and each following line starts
+/// with its rank, highest first, so that the labels match what
+/// `parse_pos` reads.
+pub fn board_lines(board: &Board, highlight: &dyn Fn(Pos) -> Option<Highlight>, style: Style) -> Vec<String> {
+    let mut draw_board = DrawableBoard::new();
+    board.draw_board(&mut draw_board);
+    let size = draw_board.board.len();
+    let (dim, reset) = match style {
+        Style::Colored(_) => (format!("{}", color::Fg(color::LightBlack)),
+                              format!("{}{}", color::Fg(color::Reset), color::Bg(color::Reset))),
+        Style::Plain => (String::new(), String::new()),
+    };
+
+    let mut header = format!("{}{:width$}", dim, "", width = LABEL_WIDTH as usize);
+    for c in 1..size-1 {
+        header.push_str(&format!("{} ", (b'a' + c as u8 - 1) as char));
+    }
+    header.push_str(&reset);
+
+    let mut lines = vec![header];
+    for r in (1..size-1).rev() {
+        let mut line = format!("{}{:>2} ", dim, r);
+        for c in 1..size-1 {
+            let dt = draw_board.board[r][c];
+            let hl = highlight(Pos { row: r as i8, col: c as i8 });
+            match style {
+                Style::Colored(theme) => {
+                    let token = render_token(dt, (r+c)%2 == 0, hl, &theme);
+                    line.push_str(&token);
+                    line.push_str(&token);
+                },
+                Style::Plain => {
+                    line.push(plain_char(dt, hl));
+                    line.push(plain_marker(hl));
+                },
+            }
+        }
+        line.push_str(&reset);
+        lines.push(line);
+    }
+    lines
+}
+
+fn render_token(dt: DrawableToken, even: bool, hl: Option<Highlight>, theme: &Theme) -> String {
+    format!("{}{}{}", token_fg(dt, theme), token_bg(even, hl, theme), token_char(dt))
+}
+
+fn token_char(dt: DrawableToken) -> String {
+    match dt {
+        DrawableToken::Empty => String::from(" "),
+        DrawableToken::Wall => String::from("#"),
+        DrawableToken::Piece(Team::Red) => String::from("R"),
+        DrawableToken::Piece(Team::Blue) => String::from("B"),
+    }
+}
+
+/// Like `Board::pprint`, with territory shown on empty squares.
+fn plain_char(dt: DrawableToken, hl: Option<Highlight>) -> char {
+    match (dt, hl) {
+        (DrawableToken::Empty, Some(Highlight::Territory(Ownership::Owned(Team::Red)))) => 'r',
+        (DrawableToken::Empty, Some(Highlight::Territory(Ownership::Owned(Team::Blue)))) => 'b',
+        (DrawableToken::Empty, Some(Highlight::Territory(Ownership::Tied))) => '=',
+        (DrawableToken::Empty, Some(Highlight::Territory(Ownership::Unreachable))) => '-',
+        (DrawableToken::Empty, _) => '.',
+        (DrawableToken::Wall, _) => '#',
+        (DrawableToken::Piece(Team::Red), _) => 'R',
+        (DrawableToken::Piece(Team::Blue), _) => 'B',
+    }
+}
+
+/// Stands in for background colors after a square's character.
+fn plain_marker(hl: Option<Highlight>) -> char {
+    match hl {
+        Some(Highlight::Cursor) => '<',
+        Some(Highlight::Selected) => '!',
+        Some(Highlight::Reachable) | Some(Highlight::Target) => '*',
+        Some(Highlight::LastFrom) | Some(Highlight::LastTo) | Some(Highlight::LastShot) => '\'',
+        Some(Highlight::Territory(_)) | None => ' ',
+    }
+}
+
+fn token_fg(dt: DrawableToken, theme: &Theme) -> String {
+    match dt {
+        DrawableToken::Empty => format!("{}", color::Fg(color::LightBlack)),
+        DrawableToken::Wall => format!("{}", color::Fg(color::White)),
+        DrawableToken::Piece(Team::Red) => fg(theme.red),
+        DrawableToken::Piece(Team::Blue) => fg(theme.blue),
+    }
+}
+
+fn token_bg(even: bool, hl: Option<Highlight>, theme: &Theme) -> String {
+    match hl {
+        Some(Highlight::Cursor) => return bg(theme.cursor),
+        Some(Highlight::Selected) => return bg(theme.selected),
+        Some(Highlight::Reachable) => return bg(theme.reachable),
+        Some(Highlight::Target) => return bg(theme.target),
+        Some(Highlight::LastFrom) => return bg(theme.last_from),
+        Some(Highlight::LastTo) => return bg(theme.last_to),
+        Some(Highlight::LastShot) => return bg(theme.last_shot),
+        Some(Highlight::Territory(Ownership::Owned(Team::Red))) => return bg(theme.red_area),
+        Some(Highlight::Territory(Ownership::Owned(Team::Blue))) => return bg(theme.blue_area),
+        Some(Highlight::Territory(Ownership::Tied)) => return bg(theme.tied_area),
+        Some(Highlight::Territory(Ownership::Unreachable)) => return bg(theme.unreachable_area),
+        None => {},
+    }
+
+    if even {
+        bg(theme.even)
+    } else {
+        bg(theme.odd)
+    }
+}
+
+fn fg((r, g, b): Rgb) -> String {
+    format!("{}", color::Fg(color::Rgb(r, g, b)))
+}
+
+fn bg((r, g, b): Rgb) -> String {
+    format!("{}", color::Bg(color::Rgb(r, g, b)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_territory_reads_back() {
+        let mut amazons = Amazons::new_8x8();
+        for _ in 0..6 {
+            let team = amazons.to_move();
+            let board = amazons.curr_board().clone();
+            let next = board.successors(team).nth(17).unwrap();
+            let mv = board.diff_move(&next).unwrap();
+            amazons.player_move(team, mv.player.pos, mv.new_pos, mv.new_shot).unwrap();
+
+            let territory = amazons.territory(EvalStrategy::QueenDistance);
+            assert!(territory.count(Ownership::Owned(Team::Blue)) > 0);
+            let highlight = |p| territory.owner(p).map(Highlight::Territory);
+            let lines = board_lines(amazons.curr_board(), &highlight, Style::Plain);
+            let read = Board::from_diagram(&lines.join("\n")).unwrap();
+            assert_eq!(read.pprint(), amazons.curr_board().pprint());
+        }
+    }
+
+    #[test]
+    fn lower_case_letters_are_not_pieces() {
+        let board = Board::from_diagram("Rb.\nrB.\n=-.").unwrap();
+        assert_eq!(board.players().count(), 2);
+    }
+}
@@ -0,0 +1,663 @@
+use crate::solver::*;
+use crate::solver::board::*;
+use crate::solver::notation::*;
+
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+const HELP: &str = "\
+commands:
+  list                      games in the lobby
+  create <5|8|10> <name>    start a new game
+  join <id> <red|blue>      take an open seat, replies with a reconnect token
+  seat <id> <red|blue> ai [queen|king]
+                            put an AI in an open seat
+  reconnect <token>         take back your seat after a disconnect
+  watch <id>                follow a game without playing
+  board                     show your game
+  move <from-to/shot>       play a move in your game, e.g. move c3-c5/e5
+  quit";
+
+/// Who sits in a team's chair.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Seat {
+    Open,
+    /// Carries the reconnect token.
+    Human(String),
+    Ai(EvalStrategy),
+}
+
+/// One game hosted by the lobby, mirrored to `path`.
+struct Game {
+    id: u32,
+    name: String,
+    amazons: Amazons,
+    seats: HashMap<Team, Seat>,
+    watchers: Vec<TcpStream>,
+    path: PathBuf,
+}
+
+impl Game {
+    fn new(id: u32, name: String, amazons: Amazons, dir: &Path) -> Game {
+        let mut seats = HashMap::new();
+        for t in Team::teams() {
+            seats.insert(t, Seat::Open);
+        }
+        Game {
+            id,
+            name,
+            amazons,
+            seats,
+            watchers: Vec::new(),
+            path: dir.join(format!("{}.game", id)),
+        }
+    }
+
+    /// Replay a game file written by `save`.
+    fn load(id: u32, path: &Path) -> Result<Game, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut name = String::new();
+        let mut amazons = None;
+        let mut seats = HashMap::new();
+        let mut moves = Vec::new();
+
+        for line in text.lines() {
+            let (key, rest) = split_word(line);
+            match key {
+                "name" => name = rest.to_string(),
+                "size" => amazons = Some(new_game(rest).ok_or(format!("bad size {:?}", rest))?),
+                "red" | "blue" => {
+                    let seat = parse_seat(rest).ok_or(format!("bad seat {:?}", rest))?;
+                    seats.insert(parse_team(key).unwrap(), seat);
+                },
+                "move" => moves.push(parse_move(rest).ok_or(format!("bad move {:?}", rest))?),
+                "" => {},
+                _ => return Err(format!("unknown line {:?}", line)),
+            }
+        }
+
+        let amazons = amazons.ok_or("missing size")?;
+        let mut game = Game::new(id, name, amazons, path.parent().unwrap_or_else(|| Path::new(".")));
+        game.path = path.to_path_buf();
+        for (team, seat) in seats {
+            game.seats.insert(team, seat);
+        }
+        for (p, m, s) in moves {
+            let team = game.amazons.to_move();
+            game.amazons.player_move(team, p, m, s)?;
+        }
+        Ok(game)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let mut out = String::new();
+        out.push_str(&format!("name {}\n", self.name));
+        out.push_str(&format!("size {}\n", self.amazons.board_size() - 2));
+        for t in Team::teams() {
+            out.push_str(&format!("{} {}\n", team_name(t), seat_name(&self.seats[&t])));
+        }
+        for mv in self.amazons.moves() {
+            out.push_str(&format!("move {}\n", mv));
+        }
+
+        // Write then rename so a crash never leaves half a game behind.
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    fn save_logged(&self) {
+        if let Err(e) = self.save() {
+            eprintln!("could not save game {}: {}", self.id, e);
+        }
+    }
+
+    /// The team that won, if the side to move is stuck.
+    fn winner(&self) -> Option<Team> {
+        let team = self.amazons.to_move();
+        if self.amazons.is_stuck(team) {
+            Some(team.other())
+        } else {
+            None
+        }
+    }
+
+    fn status(&self) -> String {
+        match self.winner() {
+            Some(t) => format!("winner {}", team_name(t)),
+            None => format!("turn {}", team_name(self.amazons.to_move())),
+        }
+    }
+
+    fn summary(&self) -> String {
+        format!("game {} size {} ply {} red {} blue {} {} name {}",
+                self.id, self.amazons.board_size() - 2, self.amazons.ply(),
+                seat_kind(&self.seats[&Team::Red]), seat_kind(&self.seats[&Team::Blue]),
+                self.status(), self.name)
+    }
+
+    fn board_message(&self) -> String {
+        format!("board {}\n{}{}\n", self.id, self.amazons.curr_board().pprint(), self.status())
+    }
+
+    /// Follow the game on `out`, unless that connection already does.
+    fn watch(&mut self, out: &TcpStream) -> io::Result<()> {
+        let peer = out.peer_addr()?;
+        if !self.watchers.iter().any(|w| w.peer_addr().ok() == Some(peer)) {
+            self.watchers.push(out.try_clone()?);
+        }
+        Ok(())
+    }
+
+    /// Send the current board to everyone following the game,
+    /// forgetting connections that have gone away.
+    fn broadcast(&mut self) {
+        let msg = self.board_message();
+        self.watchers.retain(|mut w| w.write_all(msg.as_bytes()).is_ok());
+    }
+}
+
+/// All games known to the server.
+struct Lobby {
+    dir: PathBuf,
+    games: BTreeMap<u32, Arc<Mutex<Game>>>,
+    next_id: u32,
+}
+
+impl Lobby {
+    /// Resume every game saved in `dir`.
+    fn open(dir: &Path) -> io::Result<Lobby> {
+        fs::create_dir_all(dir)?;
+        let mut lobby = Lobby {
+            dir: dir.to_path_buf(),
+            games: BTreeMap::new(),
+            next_id: 1,
+        };
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("game") {
+                continue;
+            }
+            let id = match path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse().ok()) {
+                Some(id) => id,
+                None => continue,
+            };
+            match Game::load(id, &path) {
+                Ok(game) => {
+                    lobby.next_id = lobby.next_id.max(id + 1);
+                    lobby.games.insert(id, Arc::new(Mutex::new(game)));
+                },
+                Err(e) => eprintln!("skipping saved game: {}", e),
+            }
+        }
+        Ok(lobby)
+    }
+
+    fn create(&mut self, name: String, amazons: Amazons) -> Arc<Mutex<Game>> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let game = Game::new(id, name, amazons, &self.dir);
+        game.save_logged();
+        let game = Arc::new(Mutex::new(game));
+        self.games.insert(id, game.clone());
+        game
+    }
+
+    fn games(&self) -> Vec<Arc<Mutex<Game>>> {
+        self.games.values().cloned().collect()
+    }
+}
+
+/// The game and team of the seat with a reconnect token. Games are
+/// locked one at a time, so call it without the lobby locked.
+fn find_token(games: &[Arc<Mutex<Game>>], token: &str) -> Option<(Arc<Mutex<Game>>, Team)> {
+    for game in games {
+        let g = game.lock().unwrap();
+        for (&team, seat) in &g.seats {
+            if *seat == Seat::Human(token.to_string()) {
+                return Some((game.clone(), team));
+            }
+        }
+    }
+    None
+}
+
+/// Accept connections on `addr` and serve the lobby stored in `dir`.
+pub fn serve(addr: &str, dir: &Path) -> io::Result<()> {
+    let lobby = Arc::new(Mutex::new(Lobby::open(dir)?));
+    let listener = TcpListener::bind(addr)?;
+    println!("Lobby listening on {} with {} saved games",
+             addr, lobby.lock().unwrap().games.len());
+
+    let games = lobby.lock().unwrap().games();
+    for game in games {
+        spawn_ai(game);
+    }
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let lobby = lobby.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle_client(lobby, stream) {
+                eprintln!("client error: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn spawn_ai(game: Arc<Mutex<Game>>) {
+    thread::spawn(move || play_ai(&game));
+}
+
+/// Let AI seats move until a human is to play or the game ends. The
+/// game is only locked to start a search and to play its move, so the
+/// lobby can list and join it while the AI thinks.
+fn play_ai(game: &Mutex<Game>) {
+    loop {
+        let handle = {
+            let g = game.lock().unwrap();
+            let team = g.amazons.to_move();
+            match g.seats[&team] {
+                Seat::Ai(s) if g.winner().is_none() => g.amazons.start_search(team, s),
+                _ => return,
+            }
+        };
+        let result = handle.join();
+        let mut g = game.lock().unwrap();
+        // None if the AI gives up, or if another thread moved first
+        // and carries on from there.
+        if g.amazons.play_search(result).is_none() {
+            return;
+        }
+        g.save_logged();
+        g.broadcast();
+    }
+}
+
+fn handle_client(lobby: Arc<Mutex<Lobby>>, stream: TcpStream) -> io::Result<()> {
+    let mut out = stream.try_clone()?;
+    let reader = BufReader::new(stream);
+    // The game and seat this connection plays for.
+    let mut session: Option<(Arc<Mutex<Game>>, Team)> = None;
+
+    writeln!(out, "amazons lobby, 'help' lists commands")?;
+    for line in reader.lines() {
+        let line = line?;
+        let (cmd, rest) = split_word(line.trim());
+        let args: Vec<&str> = rest.split_ascii_whitespace().collect();
+
+        match cmd {
+            "help" => writeln!(out, "{}", HELP)?,
+            "list" => {
+                let games = lobby.lock().unwrap().games();
+                for game in games {
+                    writeln!(out, "{}", game.lock().unwrap().summary())?;
+                }
+                writeln!(out, "end")?;
+            },
+            "create" => {
+                let (size, name) = split_word(rest);
+                match new_game(size) {
+                    Some(amazons) => {
+                        let game = lobby.lock().unwrap().create(name.to_string(), amazons);
+                        writeln!(out, "ok created {}", game.lock().unwrap().id)?;
+                    },
+                    None => writeln!(out, "error size must be 5, 8 or 10")?,
+                }
+            },
+            "join" => {
+                let game = match args.as_slice() {
+                    [id, team] => lookup(&lobby, id).zip(parse_team(team)),
+                    _ => None,
+                };
+                let (game, team) = match game {
+                    Some(it) => it,
+                    None => {
+                        writeln!(out, "error usage: join <id> <red|blue>")?;
+                        continue;
+                    },
+                };
+                let mut g = game.lock().unwrap();
+                if g.seats[&team] != Seat::Open {
+                    writeln!(out, "error seat is taken")?;
+                    continue;
+                }
+                let token = new_token();
+                g.seats.insert(team, Seat::Human(token.clone()));
+                g.save_logged();
+                g.watch(&out)?;
+                writeln!(out, "ok token {}", token)?;
+                write!(out, "{}", g.board_message())?;
+                drop(g);
+                session = Some((game, team));
+            },
+            "seat" => {
+                let request = match args.as_slice() {
+                    [id, team, "ai"] => lookup(&lobby, id).zip(parse_team(team))
+                        .map(|(g, t)| (g, t, EvalStrategy::QueenDistance)),
+                    [id, team, "ai", kind] => lookup(&lobby, id).zip(parse_team(team))
+                        .zip(parse_strategy(kind)).map(|((g, t), s)| (g, t, s)),
+                    _ => None,
+                };
+                let (game, team, strategy) = match request {
+                    Some(it) => it,
+                    None => {
+                        writeln!(out, "error usage: seat <id> <red|blue> ai [queen|king]")?;
+                        continue;
+                    },
+                };
+                let mut g = game.lock().unwrap();
+                if g.seats[&team] != Seat::Open {
+                    writeln!(out, "error seat is taken")?;
+                    continue;
+                }
+                g.seats.insert(team, Seat::Ai(strategy));
+                g.save_logged();
+                writeln!(out, "ok")?;
+                drop(g);
+                spawn_ai(game);
+            },
+            "reconnect" => {
+                let games = lobby.lock().unwrap().games();
+                let found = find_token(&games, rest);
+                match found {
+                    Some((game, team)) => {
+                        let mut g = game.lock().unwrap();
+                        g.watch(&out)?;
+                        writeln!(out, "ok game {} {}", g.id, team_name(team))?;
+                        write!(out, "{}", g.board_message())?;
+                        drop(g);
+                        session = Some((game, team));
+                    },
+                    None => writeln!(out, "error unknown token")?,
+                }
+            },
+            "watch" => match lookup(&lobby, rest) {
+                Some(game) => {
+                    let mut g = game.lock().unwrap();
+                    g.watch(&out)?;
+                    write!(out, "{}", g.board_message())?;
+                },
+                None => writeln!(out, "error no such game")?,
+            },
+            "board" => match &session {
+                Some((game, _)) => write!(out, "{}", game.lock().unwrap().board_message())?,
+                None => writeln!(out, "error not in a game")?,
+            },
+            "move" => {
+                let (game, team) = match &session {
+                    Some(it) => it.clone(),
+                    None => {
+                        writeln!(out, "error not in a game")?;
+                        continue;
+                    },
+                };
+                let (p, m, s) = match parse_move(rest) {
+                    Some(it) => it,
+                    None => {
+                        writeln!(out, "error could not parse coords")?;
+                        continue;
+                    },
+                };
+                let mut g = game.lock().unwrap();
+                if g.winner().is_some() {
+                    writeln!(out, "error game is over")?;
+                } else if g.amazons.to_move() != team {
+                    writeln!(out, "error not your turn")?;
+                } else {
+                    match g.amazons.player_move(team, p, m, s) {
+                        Ok(()) => {
+                            writeln!(out, "ok")?;
+                            g.save_logged();
+                            g.broadcast();
+                            drop(g);
+                            spawn_ai(game);
+                        },
+                        Err(msg) => writeln!(out, "error {}", msg)?,
+                    }
+                }
+            },
+            "quit" => break,
+            "" => {},
+            _ => writeln!(out, "error unknown command, try 'help'")?,
+        }
+    }
+    Ok(())
+}
+
+fn lookup(lobby: &Arc<Mutex<Lobby>>, id: &str) -> Option<Arc<Mutex<Game>>> {
+    let id: u32 = id.trim().parse().ok()?;
+    lobby.lock().unwrap().games.get(&id).cloned()
+}
+
+/// An unguessable reconnect token.
+fn new_token() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut h = RandomState::new().build_hasher();
+    h.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    format!("{:016x}", h.finish())
+}
+
+fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim()),
+        None => (s, ""),
+    }
+}
+
+fn new_game(size: &str) -> Option<Amazons> {
+    match size {
+        "5" => Some(Amazons::new_5x5()),
+        "8" => Some(Amazons::new_8x8()),
+        "10" => Some(Amazons::new_10x10()),
+        _ => None,
+    }
+}
+
+fn parse_team(s: &str) -> Option<Team> {
+    match s {
+        "red" => Some(Team::Red),
+        "blue" => Some(Team::Blue),
+        _ => None,
+    }
+}
+
+fn team_name(t: Team) -> &'static str {
+    match t {
+        Team::Red => "red",
+        Team::Blue => "blue",
+    }
+}
+
+fn parse_strategy(s: &str) -> Option<EvalStrategy> {
+    match s {
+        "queen" => Some(EvalStrategy::QueenDistance),
+        "king" => Some(EvalStrategy::KingDistance),
+        _ => None,
+    }
+}
+
+fn parse_seat(s: &str) -> Option<Seat> {
+    let (kind, rest) = split_word(s);
+    match kind {
+        "open" => Some(Seat::Open),
+        "human" if !rest.is_empty() => Some(Seat::Human(rest.to_string())),
+        "ai" => parse_strategy(rest).map(Seat::Ai),
+        _ => None,
+    }
+}
+
+fn seat_name(seat: &Seat) -> String {
+    match seat {
+        Seat::Open => String::from("open"),
+        Seat::Human(token) => format!("human {}", token),
+        Seat::Ai(EvalStrategy::QueenDistance) => String::from("ai queen"),
+        Seat::Ai(EvalStrategy::KingDistance) => String::from("ai king"),
+    }
+}
+
+/// Like `seat_name` but without secrets, for the lobby listing.
+fn seat_kind(seat: &Seat) -> &'static str {
+    match seat {
+        Seat::Open => "open",
+        Seat::Human(_) => "human",
+        Seat::Ai(EvalStrategy::QueenDistance) => "ai-queen",
+        Seat::Ai(EvalStrategy::KingDistance) => "ai-king",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for each test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("amazons-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Some legal move for the team to move.
+    fn any_move(amazons: &Amazons) -> Move {
+        let board = amazons.curr_board();
+        let next = board.successors(amazons.to_move()).next().unwrap();
+        board.diff_move(&next).unwrap()
+    }
+
+    #[test]
+    fn saved_game_loads_back() {
+        let dir = test_dir("save");
+        let mut game = Game::new(3, String::from("a name"), Amazons::new_5x5(), &dir);
+        game.seats.insert(Team::Red, Seat::Human(String::from("0123456789abcdef")));
+        game.seats.insert(Team::Blue, Seat::Ai(EvalStrategy::KingDistance));
+        for _ in 0..3 {
+            let mv = any_move(&game.amazons);
+            game.amazons.player_move(mv.player.team, mv.player.pos, mv.new_pos, mv.new_shot).unwrap();
+        }
+        game.save().unwrap();
+
+        let loaded = Game::load(3, &game.path).unwrap();
+        assert_eq!(loaded.name, game.name);
+        assert_eq!(loaded.seats, game.seats);
+        assert_eq!(loaded.amazons.moves(), game.amazons.moves());
+        assert_eq!(loaded.amazons.curr_board(), game.amazons.curr_board());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A connection to a lobby served in `dir`.
+    struct Client {
+        out: TcpStream,
+        lines: io::Lines<BufReader<TcpStream>>,
+    }
+
+    impl Client {
+        fn connect(dir: &Path) -> Client {
+            let lobby = Arc::new(Mutex::new(Lobby::open(dir).unwrap()));
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            thread::spawn(move || {
+                let (stream, _) = listener.accept().unwrap();
+                handle_client(lobby, stream).unwrap();
+            });
+            let stream = TcpStream::connect(addr).unwrap();
+            let mut client = Client { out: stream.try_clone().unwrap(), lines: BufReader::new(stream).lines() };
+            assert_eq!(client.line(), "amazons lobby, 'help' lists commands");
+            client
+        }
+
+        fn send(&mut self, line: &str) {
+            writeln!(self.out, "{}", line).unwrap();
+        }
+
+        fn line(&mut self) -> String {
+            self.lines.next().unwrap().unwrap()
+        }
+
+        /// The header, diagram and status of a board message.
+        fn board(&mut self) -> (String, String, String) {
+            let header = self.line();
+            let diagram: Vec<String> = (0..7).map(|_| self.line()).collect();
+            (header, diagram.join("\n"), self.line())
+        }
+    }
+
+    #[test]
+    fn lobby_protocol() {
+        let dir = test_dir("protocol");
+        let mut client = Client::connect(&dir);
+
+        client.send("create 5 first game");
+        assert_eq!(client.line(), "ok created 1");
+        client.send("create 6 bad");
+        assert_eq!(client.line(), "error size must be 5, 8 or 10");
+        client.send("list");
+        assert_eq!(client.line(), "game 1 size 5 ply 0 red open blue open turn red name first game");
+        assert_eq!(client.line(), "end");
+
+        client.send("move a1-a2/a3");
+        assert_eq!(client.line(), "error not in a game");
+        client.send("join 1 red");
+        let token = client.line().strip_prefix("ok token ").unwrap().to_string();
+        let (header, diagram, status) = client.board();
+        assert_eq!(header, "board 1");
+        assert_eq!(diagram, Amazons::new_5x5().curr_board().pprint().trim_end());
+        assert_eq!(status, "turn red");
+        client.send("join 1 red");
+        assert_eq!(client.line(), "error seat is taken");
+
+        let mv = any_move(&Amazons::new_5x5());
+        client.send(&format!("move {}", mv));
+        assert_eq!(client.line(), "ok");
+        assert_eq!(client.board().2, "turn blue");
+        client.send(&format!("move {}", mv));
+        assert_eq!(client.line(), "error not your turn");
+
+        client.send("seat 1 blue ai king");
+        assert_eq!(client.line(), "ok");
+        let (header, _, status) = client.board();
+        assert_eq!((header.as_str(), status.as_str()), ("board 1", "turn red"));
+        client.send("list");
+        assert_eq!(client.line(), "game 1 size 5 ply 2 red human blue ai-king turn red name first game");
+        assert_eq!(client.line(), "end");
+
+        client.send(&format!("reconnect {}", token));
+        assert_eq!(client.line(), "ok game 1 red");
+        let (_, diagram, status) = client.board();
+        assert_eq!(status, "turn red");
+        client.send("watch 1");
+        assert_eq!(client.board().2, "turn red");
+        // Still one copy of each update, then the AI's reply.
+        let board = Board::from_diagram(&diagram).unwrap();
+        let next = board.successors(Team::Red).next().unwrap();
+        client.send(&format!("move {}", board.diff_move(&next).unwrap()));
+        assert_eq!(client.line(), "ok");
+        assert_eq!(client.board().2, "turn blue");
+        assert_ne!(client.board().2, "turn blue");
+        client.send("reconnect nobody");
+        assert_eq!(client.line(), "error unknown token");
+        client.send("create 10 big game");
+        assert_eq!(client.line(), "ok created 2");
+        client.send("quit");
+
+        // The lobby comes back from its files.
+        let lobby = Lobby::open(&dir).unwrap();
+        let game = lobby.games[&1].lock().unwrap();
+        assert_eq!(game.amazons.ply(), 4);
+        assert_eq!(game.seats[&Team::Blue], Seat::Ai(EvalStrategy::KingDistance));
+        drop(game);
+        assert_eq!(lobby.games[&2].lock().unwrap().amazons.board_size(), 10 + 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
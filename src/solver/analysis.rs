@@ -0,0 +1,238 @@
+use super::*;
+use super::record::*;
+
+/// Points a move may lose against the best one found before it is an
+/// inaccuracy.
+pub const INACCURACY: i64 = 3;
+/// Points a move may lose before it is a blunder.
+pub const BLUNDER: i64 = 6;
+
+/// What a lost-points threshold makes of a move.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Judgement {
+    Inaccuracy,
+    Blunder,
+}
+
+impl Judgement {
+    /// The remark written after the move in a record.
+    pub fn glyph(&self) -> &'static str {
+        match self {
+            Judgement::Inaccuracy => "?!",
+            Judgement::Blunder => "??",
+        }
+    }
+}
+
+/// The search's view of one move of a game.
+#[derive(Clone, Debug)]
+pub struct PlyAnalysis {
+    pub ply: usize,
+    pub team: Team,
+    pub played: Move,
+    /// Score of the move played, for the team that played it.
+    pub played_score: i64,
+    pub best: Move,
+    pub best_score: i64,
+}
+
+impl PlyAnalysis {
+    /// Points lost against the best move.
+    pub fn loss(&self) -> i64 {
+        self.best_score.saturating_sub(self.played_score)
+    }
+
+    pub fn judgement(&self) -> Option<Judgement> {
+        match self.loss() {
+            l if l >= BLUNDER => Some(Judgement::Blunder),
+            l if l >= INACCURACY => Some(Judgement::Inaccuracy),
+            _ => None,
+        }
+    }
+
+    /// The score after the move, for Red.
+    pub fn red_eval(&self) -> i64 {
+        match self.team {
+            Team::Red => self.played_score,
+            Team::Blue => self.played_score.saturating_neg(),
+        }
+    }
+}
+
+/// Search every position of a record's main line `depth` moves deep,
+/// scoring the move played against the best move found.
+pub fn analyze(record: &GameRecord, depth: i32, strategy: EvalStrategy) -> Result<Vec<PlyAnalysis>, String> {
+    let mut amazons = record.start()?;
+    let mut analysis = Vec::new();
+    for (i, &(p, m, s)) in record.line.moves.iter().enumerate() {
+        let team = amazons.to_move();
+        let before = amazons.curr_board().clone();
+        amazons.player_move(team, p, m, s)
+            .map_err(|msg| format!("ply {} ({}-{}/{}): {}", i + 1, p, m, s, msg))?;
+        let after = amazons.curr_board().clone();
+        let search = &mut Search::default();
+
+        let played = before.diff_move(&after).ok_or(format!("ply {} changes nothing", i + 1))?;
+        let played_score = match depth {
+            d if d <= 1 => after.evaluate(team, strategy, &mut search.regions),
+            d => max_move(&after, team.other(), strategy, d - 1, 1, search).1.saturating_neg(),
+        };
+        // The best move is only looked for among the candidates worth
+        // searching, so the move played may beat it.
+        let (best, best_score) = match ranked_moves(&before, team, strategy, depth, search).into_iter().next() {
+            Some((score, b)) if score > played_score => (before.diff_move(&b).unwrap(), score),
+            _ => (played.clone(), played_score),
+        };
+        analysis.push(PlyAnalysis { ply: i + 1, team, played, played_score, best, best_score });
+    }
+    Ok(analysis)
+}
+
+/// The record with inaccuracies and blunders marked on its main line,
+/// each with a comment naming the better move.
+pub fn annotate(record: &GameRecord, analysis: &[PlyAnalysis], depth: i32) -> GameRecord {
+    let mut annotated = record.clone();
+    annotated.set_tag("Annotator", &format!("amazons --analyze, depth {}", depth));
+    let line = &mut annotated.line;
+    for a in analysis {
+        if let Some(judgement) = a.judgement() {
+            let at = a.ply - 1;
+            line.glyphs.retain(|(i, _)| *i != at);
+            line.glyphs.push((at, judgement.glyph().to_string()));
+            line.comments.push((at, format!("{:?} loses {}; best was {} ({})",
+                                            judgement, a.loss(), a.best, format_score(a.best_score))));
+        }
+    }
+    annotated
+}
+
+/// One row per move, with a header.
+pub fn eval_csv(analysis: &[PlyAnalysis]) -> String {
+    let mut csv = String::from("ply,team,move,score,red_eval,best,best_score,loss,judgement\n");
+    for a in analysis {
+        csv.push_str(&format!("{},{:?},{},{},{},{},{},{},{}\n",
+                              a.ply, a.team, a.played, a.played_score, a.red_eval(),
+                              a.best, a.best_score, a.loss(),
+                              a.judgement().map_or(String::new(), |j| format!("{:?}", j))));
+    }
+    csv
+}
+
+/// Half the width of the bars in `eval_graph`.
+const GRAPH_WIDTH: i64 = 20;
+
+/// A bar per move showing the score for Red after it: bars to the
+/// right favour Red, to the left Blue.
+pub fn eval_graph(analysis: &[PlyAnalysis]) -> String {
+    // Scale to the largest score short of a won game.
+    let scale = analysis.iter()
+        .map(|a| a.red_eval().abs())
+        .filter(|&e| e < WON)
+        .max()
+        .unwrap_or(1)
+        .max(1);
+
+    let mut graph = String::new();
+    for a in analysis {
+        let eval = a.red_eval();
+        let len = (eval.clamp(-scale, scale) * GRAPH_WIDTH / scale).unsigned_abs() as usize;
+        let (left, right) = match eval {
+            e if e < 0 => (format!("{:>w$}", "#".repeat(len), w = GRAPH_WIDTH as usize), " ".repeat(GRAPH_WIDTH as usize)),
+            _ => (" ".repeat(GRAPH_WIDTH as usize), format!("{:<w$}", "#".repeat(len), w = GRAPH_WIDTH as usize)),
+        };
+        let row = format!("{:>3}. {:<4} {:<11} {:>6} {}|{} {}",
+                          a.ply, format!("{:?}", a.team), a.played.to_string(), format_score(eval),
+                          left, right, a.judgement().map_or("", |j| j.glyph()));
+        graph.push_str(row.trim_end());
+        graph.push('\n');
+    }
+    graph
+}
+
+/// Scores at least this big come from a side running out of moves.
+const WON: i64 = i64::MAX / 2;
+
+fn format_score(score: i64) -> String {
+    match score {
+        s if s >= WON => String::from("won"),
+        s if s <= -WON => String::from("lost"),
+        s => format!("{:+}", s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 5x5 game where Red opens with the move that looks worst one
+    /// move deep and Blue answers with the one that looks best.
+    fn thrown_away_game() -> GameRecord {
+        let mut amazons = Amazons::new_5x5();
+        let board = amazons.curr_board().clone();
+        let mut regions = RegionTable::new();
+        let worst = board.successors(Team::Red)
+            .min_by_key(|b| b.evaluate(Team::Red, EvalStrategy::QueenDistance, &mut regions))
+            .unwrap();
+        let reply = ranked_moves(&worst, Team::Blue, EvalStrategy::QueenDistance, 1, &mut Search::default())
+            .remove(0).1;
+        for (before, after) in [(&board, &worst), (&worst, &reply)].iter() {
+            let mv = before.diff_move(after).unwrap();
+            amazons.player_move(mv.player.team, mv.player.pos, mv.new_pos, mv.new_shot).unwrap();
+        }
+        GameRecord::from_game(&amazons)
+    }
+
+    #[test]
+    fn annotation_marks_the_thrown_away_move() {
+        let record = thrown_away_game();
+        let analysis = analyze(&record, 1, EvalStrategy::QueenDistance).unwrap();
+        assert_eq!(analysis.len(), 2);
+        assert_eq!(analysis[0].judgement(), Some(Judgement::Blunder));
+        assert_eq!(analysis[1].judgement(), None);
+        assert_eq!(analysis[1].best, analysis[1].played);
+
+        let annotated = annotate(&record, &analysis, 1);
+        assert_eq!(annotated.tag("Annotator"), Some("amazons --analyze, depth 1"));
+        assert_eq!(annotated.line.glyphs, vec![(0, String::from("??"))]);
+        assert_eq!(annotated.line.comments.len(), 1);
+        assert!(annotated.line.comments[0].1.starts_with(&format!("Blunder loses {}; best was {}",
+                                                                  analysis[0].loss(), analysis[0].best)));
+        let text = annotated.to_string();
+        assert!(text.contains(&format!("1. {}?? {{Blunder", analysis[0].played)), "{}", text);
+    }
+
+    /// Two made-up moves: an inaccuracy by Red, then a fine move by Blue.
+    fn made_up_analysis() -> Vec<PlyAnalysis> {
+        let start = Amazons::new_5x5().curr_board().clone();
+        let moves: Vec<Move> = start.successors(Team::Red).take(2).map(|b| start.diff_move(&b).unwrap()).collect();
+        vec![
+            PlyAnalysis { ply: 1, team: Team::Red, played: moves[0].clone(), played_score: 2,
+                          best: moves[1].clone(), best_score: 5 },
+            PlyAnalysis { ply: 2, team: Team::Blue, played: moves[1].clone(), played_score: 4,
+                          best: moves[1].clone(), best_score: 4 },
+        ]
+    }
+
+    #[test]
+    fn csv_has_a_row_per_move() {
+        let analysis = made_up_analysis();
+        let (a, b) = (&analysis[0].played, &analysis[1].played);
+        assert_eq!(eval_csv(&analysis), format!(
+            "ply,team,move,score,red_eval,best,best_score,loss,judgement\n\
+             1,Red,{a},2,2,{b},5,3,Inaccuracy\n\
+             2,Blue,{b},4,-4,{b},4,0,\n", a = a, b = b));
+    }
+
+    #[test]
+    fn graph_bars_lean_to_the_side_ahead() {
+        let graph = eval_graph(&made_up_analysis());
+        let rows: Vec<&str> = graph.lines().collect();
+        assert_eq!(rows.len(), 2);
+        let bar = |row: &str| row.split('|').map(|side| side.matches('#').count()).collect::<Vec<usize>>();
+        assert_eq!(bar(rows[0]), vec![0, 10]);
+        assert!(rows[0].ends_with("?!"));
+        assert!(rows[0].contains("+2"));
+        assert_eq!(bar(rows[1]), vec![20, 0]);
+        assert!(rows[1].contains("-4"));
+    }
+}
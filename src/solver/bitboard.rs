@@ -0,0 +1,272 @@
+use super::board::*;
+
+/// Most squares along a side of a board, so that the squares inside the
+/// border fit in the bits of a `u128`.
+pub const MAX_SIDE_SQUARES: i8 = 11;
+
+/// Where the squares inside the border of a board with `n` squares to a
+/// side lie in a mask: square (row, col) is bit `(row - 1) * n + col - 1`,
+/// so from a1 along each rank.
+#[derive(Clone, Copy, Debug)]
+pub struct Layout {
+    pub n: i8,
+    /// Every square's bit.
+    pub all: u128,
+    /// For each of `QUEEN_DIRS`, how far a step shifts the bits, and the
+    /// squares a step can land on without wrapping to another rank.
+    steps: [(i8, u128); 8],
+}
+
+static LAYOUTS: [Layout; MAX_SIDE_SQUARES as usize + 1] = {
+    let mut layouts = [Layout { n: 0, all: 0, steps: [(0, 0); 8] }; MAX_SIDE_SQUARES as usize + 1];
+    let mut n = 0;
+    while n <= MAX_SIDE_SQUARES {
+        layouts[n as usize] = Layout::new(n);
+        n += 1;
+    }
+    layouts
+};
+
+impl Layout {
+    const fn new(n: i8) -> Layout {
+        let all = (1u128 << (n as u32 * n as u32)) - 1;
+        let mut first_file = 0;
+        let mut last_file = 0;
+        let mut row = 0;
+        while row < n {
+            first_file |= 1 << (row as u32 * n as u32);
+            last_file |= 1 << (row as u32 * n as u32 + n as u32 - 1);
+            row += 1;
+        }
+        let mut steps = [(0, 0); 8];
+        let mut dir = 0;
+        while dir < 8 {
+            let (dr, dc) = QUEEN_DIRS[dir];
+            let lands = match dc {
+                1 => all & !first_file,
+                -1 => all & !last_file,
+                _ => all,
+            };
+            steps[dir] = (dr * n + dc, lands);
+            dir += 1;
+        }
+        Layout { n, all, steps }
+    }
+
+    /// The layout of a board of `board_size`, border included.
+    pub fn of(board_size: i8) -> &'static Layout {
+        &LAYOUTS[(board_size - 2) as usize]
+    }
+
+    /// The square's bit, or 0 if it is not inside the border.
+    pub fn bit(&self, p: Pos) -> u128 {
+        if p.row < 1 || p.row > self.n || p.col < 1 || p.col > self.n {
+            return 0;
+        }
+        1 << ((p.row - 1) as u32 * self.n as u32 + (p.col - 1) as u32)
+    }
+
+    /// The square of bit `index`.
+    pub fn pos(&self, index: u32) -> Pos {
+        Pos { row: (index / self.n as u32) as i8 + 1, col: (index % self.n as u32) as i8 + 1 }
+    }
+
+    /// The squares one step along `QUEEN_DIRS[dir]` from any in `bits`.
+    pub fn step(&self, bits: u128, dir: usize) -> u128 {
+        let (shift, lands) = self.steps[dir];
+        let moved = if shift >= 0 { bits << shift } else { bits >> -shift };
+        moved & lands
+    }
+
+    /// The squares a king step from any in `bits`.
+    pub fn neighbours(&self, bits: u128) -> u128 {
+        (0..8).fold(0, |around, dir| around | self.step(bits, dir))
+    }
+
+    /// The squares a queen can move to from any in `from`, going only
+    /// through `empty` ones.
+    pub fn slide(&self, from: u128, empty: u128) -> u128 {
+        let mut reach = 0;
+        for dir in 0..8 {
+            let mut ray = self.step(from, dir) & empty;
+            while ray != 0 {
+                reach |= ray;
+                ray = self.step(ray, dir) & empty;
+            }
+        }
+        reach
+    }
+
+    /// The squares in `bits` moved by `sym`, as `Pos::transform` moves
+    /// each one.
+    pub fn transform(&self, bits: u128, sym: Symmetry) -> u128 {
+        match sym {
+            Symmetry::Identity => bits,
+            Symmetry::Rotate180 => self.reverse(bits),
+            Symmetry::FlipRanks => self.flip_ranks(bits),
+            Symmetry::FlipFiles => self.reverse(self.flip_ranks(bits)),
+            Symmetry::Diagonal => self.transpose(bits),
+            Symmetry::Rotate90 => self.reverse(self.flip_ranks(self.transpose(bits))),
+            Symmetry::Rotate270 => self.flip_ranks(self.transpose(bits)),
+            Symmetry::AntiDiagonal => self.reverse(self.transpose(bits)),
+        }
+    }
+
+    /// Every square to the one opposite it through the centre.
+    fn reverse(&self, bits: u128) -> u128 {
+        match bits {
+            0 => 0,
+            _ => bits.reverse_bits() >> (128 - self.n as u32 * self.n as u32),
+        }
+    }
+
+    /// Rank 1 to the last rank and back.
+    fn flip_ranks(&self, bits: u128) -> u128 {
+        let n = self.n as u32;
+        let rank = (1 << n) - 1;
+        (0..n).fold(0, |flipped, row| flipped | ((bits >> (row * n)) & rank) << ((n - 1 - row) * n))
+    }
+
+    /// About the a1 diagonal.
+    fn transpose(&self, bits: u128) -> u128 {
+        let n = self.n as u32;
+        let rank = (1 << n) - 1;
+        let mut moved = 0;
+        for row in 0..n {
+            let mut files = (bits >> (row * n)) & rank;
+            while files != 0 {
+                let col = files.trailing_zeros();
+                files &= files - 1;
+                moved |= 1 << (col * n + row);
+            }
+        }
+        moved
+    }
+
+    /// The squares in `bits`, lowest bit first.
+    pub fn squares(&'static self, bits: u128) -> MaskSquares {
+        MaskSquares { layout: self, bits }
+    }
+}
+
+/// The squares of a mask, from a1 along each rank.
+#[derive(Clone, Debug)]
+pub struct MaskSquares {
+    layout: &'static Layout,
+    bits: u128,
+}
+
+impl Iterator for MaskSquares {
+    type Item = Pos;
+
+    fn next(&mut self) -> Option<Pos> {
+        if self.bits == 0 {
+            return None;
+        }
+        let index = self.bits.trailing_zeros();
+        self.bits &= self.bits - 1;
+        Some(self.layout.pos(index))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.bits.count_ones() as usize;
+        (n, Some(n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A board of `n` squares a side with about `arrows` in 16 of the
+    /// squares filled at random, and one piece.
+    fn random_board(n: i8, arrows: u64, seed: &mut u64) -> Board {
+        let mut board = Board::new(n + 2, vec![Player { team: Team::Red, pos: Pos { row: 1, col: 1 } }]);
+        for row in 1..=n {
+            for col in 1..=n {
+                *seed ^= *seed << 13;
+                *seed ^= *seed >> 7;
+                *seed ^= *seed << 17;
+                if *seed % 16 < arrows && (row, col) != (1, 1) {
+                    board.wall_set(Pos { row, col }, true);
+                }
+            }
+        }
+        board
+    }
+
+    /// The squares a queen reaches from `from`, a step at a time.
+    fn walk_queen(board: &Board, from: Pos) -> Vec<Pos> {
+        let mut reach = Vec::new();
+        for &dir in QUEEN_DIRS.iter() {
+            let mut p = from.with_offset(dir, 1);
+            while !board.wall_at(p) {
+                reach.push(p);
+                p = p.with_offset(dir, 1);
+            }
+        }
+        reach.sort_by_key(|p| (p.row, p.col));
+        reach
+    }
+
+    #[test]
+    fn bits_and_squares_agree() {
+        for n in 1..=MAX_SIDE_SQUARES {
+            let layout = Layout::of(n + 2);
+            assert_eq!(layout.all.count_ones(), (n as u32) * (n as u32));
+            for index in 0..(n as u32 * n as u32) {
+                assert_eq!(layout.bit(layout.pos(index)), 1 << index);
+            }
+            assert_eq!(layout.bit(Pos { row: 0, col: 1 }), 0);
+            assert_eq!(layout.bit(Pos { row: 1, col: n + 1 }), 0);
+            let squares: Vec<Pos> = layout.squares(layout.all).collect();
+            assert_eq!(squares.len(), (n * n) as usize);
+            assert_eq!(squares.first(), Some(&Pos { row: 1, col: 1 }));
+            if n > 1 {
+                assert_eq!(squares[1], Pos { row: 1, col: 2 });
+            }
+        }
+    }
+
+    #[test]
+    fn steps_stop_at_the_edges() {
+        for n in 1..=MAX_SIDE_SQUARES {
+            let layout = Layout::of(n + 2);
+            for from in layout.squares(layout.all) {
+                for (dir, &offset) in QUEEN_DIRS.iter().enumerate() {
+                    assert_eq!(layout.step(layout.bit(from), dir), layout.bit(from.with_offset(offset, 1)),
+                               "{}x{} from {} towards {:?}", n, n, from, offset);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transforms_move_each_square_like_its_pos() {
+        let mut seed = 0x2545_f491_4f6c_dd1d;
+        for n in 1..=MAX_SIDE_SQUARES {
+            let board = random_board(n, 6, &mut seed);
+            let layout = board.layout();
+            for &sym in Symmetry::ALL.iter() {
+                let moved = layout.squares(board.empty())
+                    .fold(0, |moved, p| moved | layout.bit(p.transform(sym, n + 2)));
+                assert_eq!(layout.transform(board.empty(), sym), moved, "{}x{} {:?}", n, n, sym);
+            }
+        }
+    }
+
+    #[test]
+    fn slides_match_a_queen_walking() {
+        let mut seed = 0x9e37_79b9_7f4a_7c15;
+        for n in 1..=MAX_SIDE_SQUARES {
+            for arrows in [0, 4, 8].iter() {
+                let board = random_board(n, *arrows, &mut seed);
+                let layout = board.layout();
+                for from in layout.squares(layout.all) {
+                    let slid: Vec<Pos> = layout.squares(layout.slide(layout.bit(from), board.empty())).collect();
+                    assert_eq!(slid, walk_queen(&board, from), "{}x{} from {}", n, n, from);
+                }
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
-use bv::BitVec;
-use std::collections::VecDeque;
+use super::bitboard::*;
+use super::endgame::*;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 /// Red or Blue.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -64,6 +67,54 @@ impl Pos {
     pub fn with_offset(&self, dir: (i8, i8), dist: i8) -> Pos {
         Pos { row: self.row + dist * dir.0, col: self.col + dist * dir.1}
     }
+    /// Where the square goes under a symmetry of a board of
+    /// `board_size`, border included.
+    pub fn transform(&self, sym: Symmetry, board_size: i8) -> Pos {
+        let max = board_size - 1;
+        let (row, col) = match sym {
+            Symmetry::Identity => (self.row, self.col),
+            Symmetry::Rotate90 => (self.col, max - self.row),
+            Symmetry::Rotate180 => (max - self.row, max - self.col),
+            Symmetry::Rotate270 => (max - self.col, self.row),
+            Symmetry::FlipRanks => (max - self.row, self.col),
+            Symmetry::FlipFiles => (self.row, max - self.col),
+            Symmetry::Diagonal => (self.col, self.row),
+            Symmetry::AntiDiagonal => (max - self.col, max - self.row),
+        };
+        Pos { row, col }
+    }
+}
+
+/// The rotations and reflections of a square board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Symmetry {
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Rank 1 swaps with the last rank.
+    FlipRanks,
+    /// The a-file swaps with the last file.
+    FlipFiles,
+    /// About the a1 diagonal.
+    Diagonal,
+    AntiDiagonal,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity, Symmetry::Rotate90, Symmetry::Rotate180, Symmetry::Rotate270,
+        Symmetry::FlipRanks, Symmetry::FlipFiles, Symmetry::Diagonal, Symmetry::AntiDiagonal,
+    ];
+
+    /// The symmetry that undoes this one.
+    pub fn inverse(self) -> Symmetry {
+        match self {
+            Symmetry::Rotate90 => Symmetry::Rotate270,
+            Symmetry::Rotate270 => Symmetry::Rotate90,
+            s => s,
+        }
+    }
 }
 
 /// Team and location.
@@ -76,25 +127,34 @@ pub struct Player {
 }
 
 /// Player and what they do.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Move {
     pub player: Player,
     pub new_pos: Pos,
     pub new_shot: Pos,
 }
 
+impl Move {
+    /// The same move on the board transformed by `sym`.
+    pub fn transform(&self, sym: Symmetry, board_size: i8) -> Move {
+        Move {
+            player: Player { team: self.player.team, pos: self.player.pos.transform(sym, board_size) },
+            new_pos: self.new_pos.transform(sym, board_size),
+            new_shot: self.new_shot.transform(sym, board_size),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct DistState {
     left: Vec<u8>,
     right: Vec<u8>,
-    next: VecDeque<(Pos, u8)>,
 }
 impl DistState {
     pub fn with_board_size(board_size: i8) -> DistState {
         DistState {
             left: vec![0; board_size as usize * board_size as usize],
             right: vec![0; board_size as usize * board_size as usize],
-            next: VecDeque::new(),
         }
     }
 }
@@ -105,6 +165,89 @@ pub enum EvalStrategy {
     KingDistance,
 }
 
+/// How far a piece gets in one move under an `EvalStrategy`, for
+/// choosing the strategy at compile time.
+pub trait Reach {
+    const STRATEGY: EvalStrategy;
+
+    /// The squares one move from any in `from`, through `empty` ones.
+    fn reach(layout: &Layout, from: u128, empty: u128) -> u128;
+}
+
+/// Queen moves, for `EvalStrategy::QueenDistance`.
+pub struct QueenMoves;
+
+impl Reach for QueenMoves {
+    const STRATEGY: EvalStrategy = EvalStrategy::QueenDistance;
+
+    fn reach(layout: &Layout, from: u128, empty: u128) -> u128 {
+        layout.slide(from, empty)
+    }
+}
+
+/// King steps, for `EvalStrategy::KingDistance`.
+pub struct KingMoves;
+
+impl Reach for KingMoves {
+    const STRATEGY: EvalStrategy = EvalStrategy::KingDistance;
+
+    fn reach(layout: &Layout, from: u128, empty: u128) -> u128 {
+        layout.neighbours(from) & empty
+    }
+}
+
+
+/// Who gets to an empty square first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Ownership {
+    Owned(Team),
+    Tied,
+    Unreachable,
+}
+
+/// Per-square result of the distance heuristic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Territory {
+    board_size: i8,
+    owners: Vec<Option<Ownership>>,
+}
+impl Territory {
+    /// Owner of an empty square, or None for walls and pieces.
+    pub fn owner(&self, p: Pos) -> Option<Ownership> {
+        self.owners[p.to_linear(self.board_size)]
+    }
+
+    /// How many empty squares have the given owner.
+    pub fn count(&self, owner: Ownership) -> usize {
+        self.owners.iter().filter(|&&o| o == Some(owner)).count()
+    }
+}
+
+/// Empty squares connected by king steps, or equally by queen moves,
+/// with the pieces next to them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub squares: Vec<Pos>,
+    /// Red's pieces next to the region.
+    pub red: Vec<Pos>,
+    /// Blue's pieces next to the region.
+    pub blue: Vec<Pos>,
+}
+
+impl Region {
+    /// The team's pieces next to the region.
+    pub fn pieces(&self, team: Team) -> &[Pos] {
+        match team {
+            Team::Red => &self.red,
+            Team::Blue => &self.blue,
+        }
+    }
+
+    /// Whether pieces of both teams can move into the region.
+    pub fn is_contested(&self) -> bool {
+        !self.red.is_empty() && !self.blue.is_empty()
+    }
+}
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum DrawableToken {
@@ -126,30 +269,23 @@ impl DrawableBoard {
 }
 
 
-const MAX_NUM_PLAYERS: usize = 4;
+/// Most pieces, of both teams together, a board can hold.
+pub const MAX_NUM_PLAYERS: usize = 8;
 
 /// Game state at an instant.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Board {
-    walls: BitVec,
+    /// The arrows and pieces, in the board's `Layout`.
+    walls: u128,
     board_size: i8,
     players_array: [Player; MAX_NUM_PLAYERS],
 }
 
 impl Board {
     pub fn new(board_size: i8, players: Vec<Player>) -> Board {
-        let mut b = BitVec::new_fill(false, (board_size*board_size) as u64);
-
-        for r in 0..board_size {
-            for c in 0..board_size {
-                if r == 0 || c == 0 || r == board_size-1 || c == board_size-1 {
-                    b.set((r * board_size + c) as u64,  true);
-                }
-            }
-        }
-        for p in &players {
-            b.set(p.pos.to_linear(board_size) as u64, true);
-        }
+        assert!(board_size >= 3 && board_size - 2 <= MAX_SIDE_SQUARES);
+        let layout = Layout::of(board_size);
+        let b = players.iter().fold(0, |b, p| b | layout.bit(p.pos));
 
         assert!(players.len() <= MAX_NUM_PLAYERS);
         assert!(players.len() >= 1);
@@ -165,11 +301,35 @@ impl Board {
             players_array: pa,
         };
     }
+    /// Side length, including the border walls.
+    pub fn board_size(&self) -> i8 {
+        self.board_size
+    }
+    /// Where the squares lie in the board's masks.
+    pub fn layout(&self) -> &'static Layout {
+        Layout::of(self.board_size)
+    }
+    /// Squares outside the border are always walls, and are left so.
     pub fn wall_set(&mut self, p: Pos, val: bool) {
-        self.walls.set(p.to_linear(self.board_size) as u64, val);
+        let bit = self.layout().bit(p);
+        if val {
+            self.walls |= bit;
+        } else {
+            self.walls &= !bit;
+        }
     }
     pub fn wall_at(&self, p: Pos) -> bool {
-        self.walls.get((p.to_linear(self.board_size)) as u64)
+        let bit = self.layout().bit(p);
+        bit == 0 || self.walls & bit != 0
+    }
+    /// The team's pieces, in the board's `Layout`.
+    pub fn pieces(&self, team: Team) -> u128 {
+        let layout = self.layout();
+        self.players().filter(|p| p.team == team).fold(0, |pieces, p| pieces | layout.bit(p.pos))
+    }
+    /// The empty squares, in the board's `Layout`.
+    pub fn empty(&self) -> u128 {
+        self.layout().all & !self.walls
     }
 
     pub fn draw_board(&self, draw: &mut DrawableBoard) {
@@ -198,25 +358,25 @@ impl Board {
         }
     }
 
+    /// ASCII diagram of the board, border included, highest row first.
+    ///
+    /// The legend, shared with `from_diagram` and the plain renderer:
+    ///
+    /// * `.` an empty square
+    /// * `#` an arrow or the border
+    /// * `R` a piece of Red
+    /// * `B` a piece of Blue
     pub fn pprint(&self) -> String {
         let mut s = String::new();
-        for r in 0..self.board_size {
+        for r in (0..self.board_size).rev() {
             for c in 0..self.board_size {
                 let pos = Pos { row: r, col: c};
                 match self.players().find(|p| p.pos == pos) {
                     Some(p) => {
-                        if !self.wall_at(pos) {
-                            if p.team == Team::Blue {
-                                s.push('b');
-                            } else {
-                                s.push('w');
-                            }
+                        if p.team == Team::Blue {
+                            s.push('B');
                         } else {
-                            if p.team == Team::Blue {
-                                s.push('B');
-                            } else {
-                                s.push('W');
-                            }
+                            s.push('R');
                         }
                     },
                     None => {
@@ -233,6 +393,188 @@ impl Board {
         return s;
     }
 
+    /// Read a diagram in the `pprint` legend: the highest rank first,
+    /// `.` for empty squares, `#` for arrows and `R`/`B` for the pieces.
+    ///
+    /// Whitespace, rank numbers at either end of a line, a line of file
+    /// letters, and the markers of the plain renderer (`'*<!`) are
+    /// ignored, and its territory marks (`r`, `b`, `=` and `-`) are read
+    /// as empty squares. If every square on the outer ring is `#` the
+    /// ring is taken to be the border, as `pprint` writes it.
+    pub fn from_diagram(diagram: &str) -> Result<Board, String> {
+        Board::read_diagram(diagram, false)
+    }
+
+    /// Read a diagram written by `pprint` before it labelled the board:
+    /// the first rank first, `W`/`w` for Red and `B`/`b` for Blue.
+    pub fn from_old_diagram(diagram: &str) -> Result<Board, String> {
+        Board::read_diagram(diagram, true)
+    }
+
+    fn read_diagram(diagram: &str, old: bool) -> Result<Board, String> {
+        let mut grid: Vec<Vec<char>> = Vec::new();
+        for line in diagram.lines() {
+            let line = line.trim();
+            if line.is_empty() || is_file_labels(line) {
+                continue;
+            }
+            let line = line.trim_matches(|c: char| c.is_ascii_digit() || c.is_whitespace());
+            grid.push(line.chars().filter(|c| !c.is_whitespace() && !"'*<!".contains(*c)).collect());
+        }
+
+        let n = grid.len();
+        if let Some((i, row)) = grid.iter().enumerate().find(|(_, row)| row.len() != n) {
+            return Err(format!("line {} of the diagram has {} squares, expected {}", i + 1, row.len(), n));
+        }
+        let ring = (0..n).all(|i| grid[0][i] == '#' && grid[n-1][i] == '#' && grid[i][0] == '#' && grid[i][n-1] == '#');
+        let (grid, n) = if n >= 3 && ring {
+            let inner: Vec<Vec<char>> = grid[1..n-1].iter().map(|row| row[1..n-1].to_vec()).collect();
+            (inner, n - 2)
+        } else {
+            (grid, n)
+        };
+
+        let mut players = Vec::new();
+        let mut arrows = Vec::new();
+        for (i, row) in grid.iter().enumerate() {
+            for (j, &c) in row.iter().enumerate() {
+                let row = if old { i + 1 } else { n - i };
+                let pos = Pos { row: row as i8, col: j as i8 + 1 };
+                match (c, old) {
+                    ('.', _) | ('r', false) | ('b', false) | ('=', false) | ('-', false) => {},
+                    ('#', _) => arrows.push(pos),
+                    ('R', false) | ('W', true) | ('w', true) => players.push(Player { team: Team::Red, pos }),
+                    ('B', _) | ('b', true) => players.push(Player { team: Team::Blue, pos }),
+                    _ => return Err(format!("unknown square {:?} in the diagram", c)),
+                }
+            }
+        }
+        Board::from_squares(n, players, &arrows)
+    }
+
+    /// A board of `n` by `n` squares, checking what `new` would assert.
+    pub fn from_squares(n: usize, players: Vec<Player>, arrows: &[Pos]) -> Result<Board, String> {
+        if n < 1 || n > MAX_SIDE_SQUARES as usize {
+            return Err(format!("{} files is not between 1 and the {} supported", n, MAX_SIDE_SQUARES));
+        }
+        if players.is_empty() {
+            return Err(String::from("no pieces on the board"));
+        }
+        if players.len() > MAX_NUM_PLAYERS {
+            return Err(format!("{} pieces is more than the {} supported", players.len(), MAX_NUM_PLAYERS));
+        }
+        let mut board = Board::new(n as i8 + 2, players);
+        for &p in arrows {
+            board.wall_set(p, true);
+        }
+        Ok(board)
+    }
+
+    /// A hash of the position, the same for boards that differ only
+    /// in the order of their pieces.
+    pub fn key(&self) -> u64 {
+        let mut players: Vec<(i8, i8, Team)> = self.players()
+            .map(|p| (p.pos.row, p.pos.col, p.team))
+            .collect();
+        players.sort_by_key(|&(row, col, _)| (row, col));
+        let mut hasher = DefaultHasher::new();
+        self.walls.hash(&mut hasher);
+        players.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The board with every piece and arrow moved by `sym`. The pieces
+    /// keep their order.
+    pub fn transform(&self, sym: Symmetry) -> Board {
+        let size = self.board_size;
+        let players = self.players()
+            .map(|p| Player { team: p.team, pos: p.pos.transform(sym, size) })
+            .collect();
+        let mut moved = Board::new(size, players);
+        for row in 1..size - 1 {
+            for col in 1..size - 1 {
+                let pos = Pos { row, col };
+                if self.wall_at(pos) {
+                    moved.wall_set(pos.transform(sym, size), true);
+                }
+            }
+        }
+        moved
+    }
+
+    /// The lexicographically smallest of the board's transforms, reading
+    /// the squares from a1 along each rank, and the symmetry that takes
+    /// the board there.
+    ///
+    /// Boards that are rotations or reflections of each other have the
+    /// same canonical board, so anything stored by position can keep one
+    /// entry for all of them under the canonical board's `key`.
+    pub fn canonical(&self) -> (Board, Symmetry) {
+        Symmetry::ALL.iter()
+            .map(|&sym| {
+                let moved = self.transform(sym);
+                (moved.squares(), moved, sym)
+            })
+            .min_by(|a, b| a.0.cmp(&b.0))
+            .map(|(_, moved, sym)| (moved, sym))
+            .unwrap()
+    }
+
+    /// What `canonical` finds, without building any boards: a key for
+    /// the board that is the same for all its rotations and reflections,
+    /// and a symmetry that takes the board to the one the key stands for.
+    ///
+    /// The key orders the transforms by their masks rather than by
+    /// `squares`, so it is not the canonical board's `key`.
+    pub fn canonical_key(&self) -> (u64, Symmetry) {
+        let layout = self.layout();
+        let red = self.pieces(Team::Red);
+        let blue = self.pieces(Team::Blue);
+        let (masks, sym) = Symmetry::ALL.iter()
+            .map(|&sym| ((layout.transform(self.walls, sym), layout.transform(red, sym),
+                          layout.transform(blue, sym)), sym))
+            .min_by_key(|&(masks, _)| masks)
+            .unwrap();
+        let mut hasher = DefaultHasher::new();
+        self.board_size.hash(&mut hasher);
+        masks.hash(&mut hasher);
+        (hasher.finish(), sym)
+    }
+
+    /// The symmetries that leave the board as it is, always including
+    /// `Symmetry::Identity`.
+    pub fn symmetries(&self) -> Vec<Symmetry> {
+        let squares = self.squares();
+        Symmetry::ALL.iter().cloned()
+            .filter(|&sym| sym == Symmetry::Identity || self.transform(sym).squares() == squares)
+            .collect()
+    }
+
+    /// What is on each square inside the border, from a1 along each rank:
+    /// 0 for empty, 1 for an arrow, 2 for Red and 3 for Blue.
+    fn squares(&self) -> Vec<u8> {
+        let mut squares = Vec::with_capacity(self.board_size as usize * self.board_size as usize);
+        for row in 1..self.board_size - 1 {
+            for col in 1..self.board_size - 1 {
+                let pos = Pos { row, col };
+                squares.push(match self.players().find(|p| p.pos == pos) {
+                    Some(p) if p.team == Team::Red => 2,
+                    Some(_) => 3,
+                    None if self.wall_at(pos) => 1,
+                    None => 0,
+                });
+            }
+        }
+        squares
+    }
+
+    /// The position after a move, or None if the moving piece is not on
+    /// the board. The move is not checked otherwise.
+    pub fn play(&self, mv: &Move) -> Option<Board> {
+        let ix = self.players().position(|p| *p == mv.player)?;
+        Some(self.with_move(ix, mv.new_pos, mv.new_shot))
+    }
+
     pub fn players(&self) -> impl Iterator<Item = &Player> {
         self.players_array.iter().filter(|p| p.pos != Pos { row:0, col: 0})
     }
@@ -249,6 +591,26 @@ impl Board {
         board
     }
 
+    /// Recover the move that turns `self` into `next`.
+    ///
+    /// Returns None if `next` is not one move after `self`.
+    pub fn diff_move(&self, next: &Board) -> Option<Move> {
+        let mut moved = self.players().zip(next.players()).filter(|(a, b)| a.pos != b.pos);
+        let (player, new_pos) = moved.next().map(|(a, b)| (*a, b.pos))?;
+        if moved.next().is_some() {
+            return None;
+        }
+        let layout = self.layout();
+        let mut shots = layout.squares(next.walls & !self.walls & !layout.bit(new_pos));
+        // An arrow shot back at the square the piece left adds no wall.
+        let new_shot = shots.next().unwrap_or(player.pos);
+        if shots.next().is_some() {
+            return None;
+        }
+        let mv = Move { player, new_pos, new_shot };
+        Some(mv).filter(|mv| self.play(mv).as_ref() == Some(next))
+    }
+
     pub fn successors<'a>(&'a self, team: Team) -> impl Iterator<Item = Board> + 'a {
         self.players().enumerate().filter(move |(_,player)| player.team == team)
             .flat_map(move |(pi, player): (usize, &'a Player)| {
@@ -260,31 +622,113 @@ impl Board {
             })
     }
 
-    pub fn evaluate(&self, team: Team, strategy: EvalStrategy, dist_state: &mut DistState) -> i64 {
+    /// Score the board for `team`, counting the moves left in regions
+    /// only one team reaches with `regions`.
+    pub fn evaluate(&self, team: Team, strategy: EvalStrategy, regions: &mut RegionTable) -> i64 {
+        match strategy {
+            EvalStrategy::QueenDistance => self.evaluate_with::<QueenMoves>(team, regions),
+            EvalStrategy::KingDistance => self.evaluate_with::<KingMoves>(team, regions),
+        }
+    }
+    /// `evaluate` with the strategy fixed at compile time.
+    pub fn evaluate_with<R: Reach>(&self, team: Team, regions: &mut RegionTable) -> i64 {
+        self.bfs_eval::<R>(team, regions)
+    }
+    /// Which team is closer to each empty square, as counted by `evaluate`.
+    pub fn territory(&self, strategy: EvalStrategy, dist_state: &mut DistState) -> Territory {
         match strategy {
-            EvalStrategy::KingDistance => {
-                self.bfs_eval(team, king_range, dist_state)
-            },
             EvalStrategy::QueenDistance => {
-                self.bfs_eval(team, queen_range, dist_state)
+                self.bfs::<QueenMoves>(Team::Red, &mut dist_state.left);
+                self.bfs::<QueenMoves>(Team::Blue, &mut dist_state.right);
             },
+            EvalStrategy::KingDistance => {
+                self.bfs::<KingMoves>(Team::Red, &mut dist_state.left);
+                self.bfs::<KingMoves>(Team::Blue, &mut dist_state.right);
+            },
+        }
+
+        let owners = dist_state.left.iter().zip(dist_state.right.iter()).enumerate()
+            .map(|(i, (&red, &blue))| {
+                let pos = Pos { row: (i / self.board_size as usize) as i8, col: (i % self.board_size as usize) as i8 };
+                if self.wall_at(pos) {
+                    None
+                } else if red < blue {
+                    Some(Ownership::Owned(Team::Red))
+                } else if blue < red {
+                    Some(Ownership::Owned(Team::Blue))
+                } else if red == u8::MAX {
+                    Some(Ownership::Unreachable)
+                } else {
+                    Some(Ownership::Tied)
+                }
+            })
+            .collect();
+        Territory { board_size: self.board_size, owners }
+    }
+
+    /// The empty squares split into regions, from a1 along each rank.
+    /// A piece can be next to several regions.
+    pub fn regions(&self) -> Vec<Region> {
+        let layout = self.layout();
+        let empty = self.empty();
+        let mut left = empty;
+        let mut regions = Vec::new();
+        while left != 0 {
+            let mut squares = left & left.wrapping_neg();
+            loop {
+                let grown = squares | layout.neighbours(squares) & empty;
+                if grown == squares {
+                    break;
+                }
+                squares = grown;
+            }
+            left &= !squares;
+            let around = layout.neighbours(squares);
+            let next_to = |team: Team| self.players()
+                .filter(|p| p.team == team && around & layout.bit(p.pos) != 0)
+                .map(|p| p.pos)
+                .collect();
+            regions.push(Region {
+                squares: layout.squares(squares).collect(),
+                red: next_to(Team::Red),
+                blue: next_to(Team::Blue),
+            });
         }
+        regions
     }
-    fn bfs_eval(&self, team: Team, succ: for<'a> fn(&'a Board, Pos, Pos) -> Box<dyn Iterator<Item = Pos> + 'a>, dist_state: &mut DistState) -> i64 {
-        self.bfs(team, succ, &mut dist_state.next, &mut dist_state.left);
-        self.bfs(team.other(), succ, &mut dist_state.next, &mut dist_state.right);
 
+    /// Count the squares each team reaches first, growing both teams'
+    /// reach a ring of squares at a time.
+    fn bfs_eval<R: Reach>(&self, team: Team, regions: &mut RegionTable) -> i64 {
+        let layout = self.layout();
+        let empty = self.empty();
+        let mut ours = self.pieces(team);
+        let mut theirs = self.pieces(team.other());
+        let (mut our_ring, mut their_ring) = (ours, theirs);
         let mut score = 0;
-        let mut is_end = true;
-        for (&a,&b) in dist_state.left.iter().zip(dist_state.right.iter()) {
-            if a < b {
-                score = score + 1;
-            }
-            if a > b {
-                score = score - 1;
-            }
-            if a != u8::max_value() && b != u8::max_value() {
-                is_end = false;
+        while our_ring | their_ring != 0 {
+            our_ring = R::reach(layout, our_ring, empty) & !ours;
+            their_ring = R::reach(layout, their_ring, empty) & !theirs;
+            ours |= our_ring;
+            theirs |= their_ring;
+            score += (our_ring & !theirs).count_ones() as i64 - (their_ring & !ours).count_ones() as i64;
+        }
+        let is_end = ours & theirs & empty == 0;
+        let one_sided = (ours ^ theirs) & empty != 0;
+        // A region only one team reaches is worth the moves the team
+        // can make there, which may be fewer than its squares.
+        if one_sided {
+            let red = self.pieces(Team::Red);
+            for (squares, pieces) in component_masks(self) {
+                let owner = match (pieces & red != 0, pieces & !red != 0) {
+                    (true, false) => Team::Red,
+                    (false, true) => Team::Blue,
+                    _ => continue,
+                };
+                if let Some(moves) = regions.moves_in(layout, owner, squares, pieces) {
+                    let shortfall = squares.count_ones() as i64 - moves as i64;
+                    score += if owner == team { -shortfall } else { shortfall };
+                }
             }
         }
         if is_end {
@@ -296,40 +740,204 @@ impl Board {
         return score;
     }
 
-    fn bfs(&self, team: Team, succ: for<'a> fn(&'a Board, Pos, Pos) -> Box<dyn Iterator<Item = Pos> + 'a>, next: &mut VecDeque<(Pos, u8)>, distances: &mut Vec<u8>) {
+    /// Moves from the team's pieces to each square, a whole ring of
+    /// squares at a time.
+    fn bfs<R: Reach>(&self, team: Team, distances: &mut Vec<u8>) {
         for i in 0..distances.len() {
-            distances[i] = u8::max_value();
-        }
-        next.clear();
-        self.players()
-            .filter(|p| p.team == team)
-            .map(|p| (p.pos, 0))
-            .for_each(|it| next.push_back(it));
-
-        while let Some((pos,depth)) = next.pop_front() {
-            for neigh in succ(self, pos, pos) {
-                let place = &mut distances[neigh.to_linear(self.board_size)];
-                if depth + 1 < *place {
-                    *place = depth + 1;
-                    next.push_back((neigh, depth+1));
-                }
+            distances[i] = u8::MAX;
+        }
+        let layout = self.layout();
+        let empty = self.empty();
+        let mut ring = self.pieces(team);
+        let mut reached = ring;
+        let mut depth = 0;
+        while ring != 0 {
+            depth += 1;
+            ring = R::reach(layout, ring, empty) & !reached;
+            reached |= ring;
+            for pos in layout.squares(ring) {
+                distances[pos.to_linear(self.board_size)] = depth;
             }
         }
     }
 }
 
-const QUEEN_DIRS: [(i8,i8); 8] = [(-1,-1),(-1,0),(-1,1),
-                                  ( 0,-1)       ,( 0,1),
-                                  ( 1,-1),( 1,0),( 1,1)];
+/// Whether a line is just file letters, like `a b c d`.
+fn is_file_labels(line: &str) -> bool {
+    line.chars().filter(|c| !c.is_whitespace())
+        .zip(b'a'..)
+        .all(|(c, expected)| c == expected as char)
+        && line.starts_with('a')
+}
+
+/// The eight directions a queen moves in, as (row, col) steps.
+pub const QUEEN_DIRS: [(i8,i8); 8] = [(-1,-1),(-1,0),(-1,1),
+                                      ( 0,-1)       ,( 0,1),
+                                      ( 1,-1),( 1,0),( 1,1)];
 
 
-fn king_range<'a>(board: &'a Board, from: Pos, blank: Pos) -> Box<dyn Iterator<Item = Pos> + 'a> {
-    Box::new(QUEEN_DIRS.iter().map(move |dir| from.with_offset(*dir, 1))
-                            .filter(move |place| !board.wall_at(*place) || *place == blank))
+/// The empty squares a queen can move to from `from`, taking `blank` to
+/// be empty too.
+pub fn queen_range(board: &Board, from: Pos, blank: Pos) -> MaskSquares {
+    let layout = board.layout();
+    layout.squares(layout.slide(layout.bit(from), board.empty() | layout.bit(blank)))
 }
 
-pub fn queen_range<'a>(board: &'a Board, from: Pos, blank: Pos) -> Box<dyn Iterator<Item = Pos> + 'a> {
-    Box::new(QUEEN_DIRS.iter().flat_map(move |dir|
-                                      (1..).map(move |dist| from.with_offset(*dir, dist))
-                                      .take_while(move |place| !board.wall_at(*place) || *place == blank)))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::Amazons;
+
+    /// Positions a few moves into games on each start, picking moves
+    /// spread over the move list.
+    fn positions() -> Vec<(Board, Team)> {
+        let mut positions = Vec::new();
+        for start in [Amazons::new_5x5(), Amazons::new_8x8(), Amazons::new_10x10()].iter() {
+            let mut board = start.curr_board().clone();
+            let mut team = Team::Red;
+            for ply in 0..6 {
+                positions.push((board.clone(), team));
+                let next: Vec<Board> = board.successors(team).collect();
+                if next.is_empty() {
+                    break;
+                }
+                board = next[(ply * 37 + 11) % next.len()].clone();
+                team = team.other();
+            }
+        }
+        positions
+    }
+
+    #[test]
+    fn transforms_are_undone_by_their_inverse() {
+        for (board, _) in positions() {
+            for &sym in Symmetry::ALL.iter() {
+                assert_eq!(board.transform(sym).transform(sym.inverse()), board, "{:?}", sym);
+                let p = Pos { row: 1, col: 2 };
+                assert_eq!(p.transform(sym, board.board_size()).transform(sym.inverse(), board.board_size()), p);
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_board_is_shared_by_every_transform() {
+        for (board, _) in positions() {
+            let (canonical, sym) = board.canonical();
+            assert_eq!(board.transform(sym).squares(), canonical.squares());
+            for &other in Symmetry::ALL.iter() {
+                let (moved, _) = board.transform(other).canonical();
+                assert_eq!(moved.squares(), canonical.squares());
+                assert_eq!(moved.key(), canonical.key());
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_key_is_shared_by_every_transform() {
+        for (board, _) in positions() {
+            let (key, sym) = board.canonical_key();
+            let moved = board.transform(sym);
+            for &other in Symmetry::ALL.iter() {
+                let (other_key, other_sym) = board.transform(other).canonical_key();
+                assert_eq!(other_key, key);
+                assert_eq!(board.transform(other).transform(other_sym).key(), moved.key());
+            }
+        }
+        let start = Amazons::new_8x8().curr_board().clone();
+        let next = start.successors(Team::Red).next().unwrap();
+        assert_ne!(next.canonical_key().0, start.canonical_key().0);
+    }
+
+    #[test]
+    fn starts_are_symmetric_left_to_right() {
+        for start in [Amazons::new_5x5(), Amazons::new_8x8(), Amazons::new_10x10()].iter() {
+            let symmetries = start.curr_board().symmetries();
+            assert_eq!(symmetries, vec![Symmetry::Identity, Symmetry::FlipFiles]);
+        }
+    }
+
+    #[test]
+    fn diff_move_recovers_every_move() {
+        for (board, team) in positions() {
+            for next in board.successors(team) {
+                let mv = board.diff_move(&next).unwrap();
+                assert_eq!(mv.player.team, team);
+                assert_eq!(board.play(&mv), Some(next));
+            }
+            assert_eq!(board.diff_move(&board), None);
+        }
+    }
+
+    #[test]
+    fn diff_move_rejects_boards_more_than_a_move_apart() {
+        let board = Board::from_diagram("R . . .\n. . . .\n. . . .\n. . . B").unwrap();
+        let red = board.with_move(0, Pos { row: 4, col: 2 }, Pos { row: 4, col: 3 });
+        let both = red.with_move(1, Pos { row: 1, col: 3 }, Pos { row: 1, col: 2 });
+        assert_eq!(board.diff_move(&both), None);
+
+        let mut two_arrows = red.clone();
+        two_arrows.wall_set(Pos { row: 2, col: 2 }, true);
+        assert_eq!(board.diff_move(&two_arrows), None);
+
+        let mut no_arrow = board.with_move(0, Pos { row: 4, col: 2 }, Pos { row: 4, col: 2 });
+        no_arrow.wall_set(Pos { row: 4, col: 1 }, false);
+        assert_eq!(board.diff_move(&no_arrow), None);
+    }
+
+    #[test]
+    fn shooting_back_at_the_start_square() {
+        let board = Board::from_diagram("R . .\n. . .\n. . B").unwrap();
+        let from = Pos { row: 3, col: 1 };
+        let to = Pos { row: 3, col: 3 };
+        let next = board.with_move(0, to, from);
+        let mv = board.diff_move(&next).unwrap();
+        assert_eq!((mv.player.pos, mv.new_pos, mv.new_shot), (from, to, from));
+    }
+
+    #[test]
+    fn regions_are_split_by_arrows() {
+        let board = Board::from_diagram("\
+            . . # . .
+            R . # . B
+            # # # # #
+            . . . . .
+            B . . R .").unwrap();
+        let square = |s: &str| crate::solver::notation::parse_pos(s).unwrap();
+        let squares = |names: &[&str]| names.iter().map(|s| square(s)).collect::<Vec<Pos>>();
+        let regions = board.regions();
+        assert_eq!(regions.len(), 3);
+
+        assert_eq!(regions[0].squares, squares(&["b1", "c1", "e1", "a2", "b2", "c2", "d2", "e2"]));
+        assert_eq!(regions[0].pieces(Team::Red), &squares(&["d1"])[..]);
+        assert_eq!(regions[0].pieces(Team::Blue), &squares(&["a1"])[..]);
+        assert!(regions[0].is_contested());
+
+        assert_eq!(regions[1].squares, squares(&["b4", "a5", "b5"]));
+        assert_eq!(regions[1].pieces(Team::Red), &squares(&["a4"])[..]);
+        assert!(regions[1].pieces(Team::Blue).is_empty());
+        assert!(!regions[1].is_contested());
+
+        assert_eq!(regions[2].squares, squares(&["d4", "d5", "e5"]));
+        assert!(regions[2].pieces(Team::Red).is_empty());
+        assert_eq!(regions[2].pieces(Team::Blue), &squares(&["e4"])[..]);
+        assert!(!regions[2].is_contested());
+    }
+
+    #[test]
+    fn old_diagrams_read_from_the_first_rank() {
+        // The 5x5 start with an arrow on c1, as `pprint` used to write it.
+        let old = "#######\n#..#..#\n#.W.W.#\n#.....#\n#.B.B.#\n#.....#\n#######\n";
+        let mut start = Amazons::new_5x5().curr_board().clone();
+        start.wall_set(Pos { row: 1, col: 3 }, true);
+        assert_eq!(Board::from_old_diagram(old), Ok(start.clone()));
+        assert_eq!(Board::from_old_diagram(&old.replace('W', "w").replace('B', "b")), Ok(start));
+        assert!(Board::from_diagram(old).is_err());
+    }
+
+    #[test]
+    fn boards_are_at_most_eleven_squares_a_side() {
+        let piece = vec![Player { team: Team::Red, pos: Pos { row: 1, col: 1 } }];
+        assert!(Board::from_squares(MAX_SIDE_SQUARES as usize, piece.clone(), &[]).is_ok());
+        assert!(Board::from_squares(MAX_SIDE_SQUARES as usize + 1, piece, &[]).is_err());
+    }
 }
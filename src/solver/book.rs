@@ -0,0 +1,300 @@
+use super::*;
+use super::notation::*;
+
+use std::collections::HashMap;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::{Arc, LazyLock};
+
+/// The books shipped for the `new_8x8` and `new_10x10` starts.
+const BOOK_8X8: &str = include_str!("../../books/8x8.book");
+const BOOK_10X10: &str = include_str!("../../books/10x10.book");
+
+/// The shipped books, read the first time a game needs them.
+static BUILTIN_8X8: LazyLock<Arc<OpeningBook>> = LazyLock::new(|| builtin_book(BOOK_8X8));
+static BUILTIN_10X10: LazyLock<Arc<OpeningBook>> = LazyLock::new(|| builtin_book(BOOK_10X10));
+
+/// Points a move may score below the best one and still be put in a
+/// book by `OpeningBook::build`.
+pub const BOOK_MARGIN: i64 = 2;
+
+/// A move from a book, and how often to play it relative to the
+/// other moves from its position.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BookMove {
+    pub mv: Move,
+    pub weight: u32,
+}
+
+/// The moves known for one position, in its canonical orientation.
+#[derive(Clone, Debug)]
+struct Entry {
+    board: Board,
+    team: Team,
+    moves: Vec<BookMove>,
+}
+
+/// Good moves by position, for the start of the game where searches
+/// are slowest and least useful.
+///
+/// Positions that are rotations or reflections of each other share
+/// an entry, so a book built from one orientation of a position is
+/// used for all of them.
+#[derive(Clone, Debug, Default)]
+pub struct OpeningBook {
+    entries: HashMap<u64, Entry>,
+}
+
+impl OpeningBook {
+    pub fn new() -> OpeningBook {
+        OpeningBook::default()
+    }
+
+    /// The book shipped for boards of this size, border included,
+    /// shared by every game that uses it.
+    pub fn builtin(board_size: i8) -> Option<Arc<OpeningBook>> {
+        match board_size - 2 {
+            8 => Some(BUILTIN_8X8.clone()),
+            10 => Some(BUILTIN_10X10.clone()),
+            _ => None,
+        }
+    }
+
+    /// Number of positions in the book.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add `weight` to a move from a position, adding the move if it is
+    /// new. The move must be legal.
+    pub fn insert(&mut self, board: &Board, team: Team, mv: &Move, weight: u32) {
+        let (canonical, sym) = board.canonical();
+        let mv = mv.transform(sym, board.board_size());
+        let entry = self.entries.entry(key(&canonical, team))
+            .or_insert_with(|| Entry { board: canonical, team, moves: Vec::new() });
+        match entry.moves.iter_mut().find(|m| m.mv == mv) {
+            Some(m) => m.weight += weight,
+            None => entry.moves.push(BookMove { mv, weight }),
+        }
+    }
+
+    /// Whether the book has moves for `team` from `board`.
+    pub fn contains(&self, board: &Board, team: Team) -> bool {
+        let (canonical, _) = board.canonical();
+        self.entries.contains_key(&key(&canonical, team))
+    }
+
+    /// The book moves for `team` from `board`, heaviest first.
+    pub fn moves(&self, board: &Board, team: Team) -> Vec<BookMove> {
+        let (canonical, sym) = board.canonical();
+        let back = sym.inverse();
+        let mut moves: Vec<BookMove> = self.entries.get(&key(&canonical, team))
+            .map_or(Vec::new(), |e| e.moves.iter()
+                .map(|m| BookMove { mv: m.mv.transform(back, board.board_size()), weight: m.weight })
+                .collect());
+        moves.sort_by_key(|m| std::cmp::Reverse(m.weight));
+        moves
+    }
+
+    /// The position after a book move for `team` from `board`, picked
+    /// at random in proportion to the weights.
+    pub fn choose(&self, board: &Board, team: Team) -> Option<Board> {
+        let moves = self.moves(board, team);
+        let total: u64 = moves.iter().map(|m| m.weight as u64).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut pick = RandomState::new().build_hasher().finish() % total;
+        let chosen = moves.iter().find(|m| {
+            if pick < m.weight as u64 {
+                return true;
+            }
+            pick -= m.weight as u64;
+            false
+        })?;
+        board.play(&chosen.mv)
+    }
+
+    /// Read a book written by the `Display` impl.
+    ///
+    /// Each line is a position as a `Board::to_fen` string, then its
+    /// moves with their weights, separated by `;`:
+    ///
+    /// `8/8/2B2B2/8/8/2R2R2/8/8 r; c3-c5/c8 3; c3-d4/g7 1`
+    ///
+    /// Lines starting with `//` are comments.
+    pub fn parse(text: &str) -> Result<OpeningBook, String> {
+        let mut book = OpeningBook::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let err = |msg: String| format!("line {}: {}", i + 1, msg);
+            let mut fields = line.split(';').map(str::trim);
+            let (board, team) = Board::from_fen(fields.next().unwrap_or("")).map_err(err)?;
+            for field in fields {
+                let (mv, weight) = field.rsplit_once(' ')
+                    .ok_or_else(|| err(format!("expected a move and a weight, not {:?}", field)))?;
+                let weight = weight.parse().map_err(|_| err(format!("bad weight {:?}", weight)))?;
+                let (from, to, shot) = parse_move(mv).ok_or_else(|| err(format!("bad move {:?}", mv)))?;
+                let player = *board.players().find(|p| p.pos == from && p.team == team)
+                    .ok_or_else(|| err(format!("no {:?} piece on {}", team, from)))?;
+                let mv = Move { player, new_pos: to, new_shot: shot };
+                if !is_legal(&board, &mv) {
+                    return Err(err(format!("{} is not a legal move", mv)));
+                }
+                book.insert(&board, team, &mv, weight);
+            }
+        }
+        Ok(book)
+    }
+
+    /// Build a book by searching every position reachable with book
+    /// moves, `plies` moves deep from `board`.
+    ///
+    /// Each position is searched `depth` moves deep, and the best
+    /// `width` moves within `BOOK_MARGIN` points of the best one go in
+    /// the book, the best weighing the most. `progress` is told of each
+    /// position before it is searched.
+    pub fn build(board: &Board, team: Team, plies: usize, width: usize, depth: i32, strategy: EvalStrategy,
+                 progress: &mut dyn FnMut(&Board, Team)) -> OpeningBook {
+        let mut book = OpeningBook::new();
+        let mut frontier = vec![(board.clone(), team)];
+        for _ in 0..plies {
+            let mut next = Vec::new();
+            for (board, team) in frontier {
+                if book.contains(&board, team) {
+                    continue;
+                }
+                progress(&board, team);
+                let ranked = ranked_moves(&board, team, strategy, depth, &mut Search::default());
+                let best = match ranked.first() {
+                    Some(&(score, _)) => score,
+                    None => continue,
+                };
+                // Moves to symmetric positions are the same move.
+                let mut seen = Vec::new();
+                for (score, after) in ranked {
+                    let loss = best.saturating_sub(score);
+                    let (canonical, _) = after.canonical();
+                    if loss > BOOK_MARGIN || seen.len() == width {
+                        break;
+                    }
+                    if seen.contains(&key(&canonical, team.other())) {
+                        continue;
+                    }
+                    seen.push(key(&canonical, team.other()));
+                    let mv = board.diff_move(&after).expect("a search returns positions one move on");
+                    book.insert(&board, team, &mv, (BOOK_MARGIN - loss + 1) as u32);
+                    next.push((after, team.other()));
+                }
+            }
+            frontier = next;
+        }
+        book
+    }
+}
+
+impl fmt::Display for OpeningBook {
+    /// One line per position as `parse` reads it, sorted so that books
+    /// built alike compare alike.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines: Vec<String> = self.entries.values()
+            .map(|e| {
+                let mut moves = e.moves.clone();
+                moves.sort_by_key(|m| (std::cmp::Reverse(m.weight), m.mv.to_string()));
+                std::iter::once(e.board.to_fen(e.team))
+                    .chain(moves.iter().map(|m| format!("{} {}", m.mv, m.weight)))
+                    .collect::<Vec<String>>()
+                    .join("; ")
+            })
+            .collect();
+        lines.sort();
+        for line in lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+fn builtin_book(text: &str) -> Arc<OpeningBook> {
+    Arc::new(OpeningBook::parse(text).expect("the shipped opening books are valid"))
+}
+
+fn key(board: &Board, team: Team) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    board.key().hash(&mut hasher);
+    team.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Whether the piece can reach `new_pos` and then shoot `new_shot`.
+fn is_legal(board: &Board, mv: &Move) -> bool {
+    let from = mv.player.pos;
+    queen_range(board, from, from).any(|p| p == mv.new_pos)
+        && queen_range(board, mv.new_pos, from).any(|p| p == mv.new_shot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every position the book's moves lead to from `start`, including
+    /// those it has no moves for.
+    fn reachable(book: &OpeningBook, start: &Board) -> Vec<(Board, Team)> {
+        let mut reached = Vec::new();
+        let mut frontier = vec![(start.clone(), Team::Red)];
+        while let Some((board, team)) = frontier.pop() {
+            for m in book.moves(&board, team) {
+                let after = board.play(&m.mv).unwrap();
+                assert!(board.successors(team).any(|b| b.key() == after.key()),
+                        "{} is not legal in {}", m.mv, board.to_fen(team));
+                frontier.push((after, team.other()));
+            }
+            reached.push((board, team));
+        }
+        reached
+    }
+
+    #[test]
+    fn builtin_lines_are_legal_from_the_start() {
+        for start in [Amazons::new_8x8(), Amazons::new_10x10()].iter() {
+            let board = start.curr_board();
+            let book = OpeningBook::builtin(board.board_size()).unwrap();
+            assert!(!book.is_empty());
+            let mut entries: Vec<u64> = reachable(&book, board).iter()
+                .filter(|(b, t)| book.contains(b, *t))
+                .map(|(b, t)| key(&b.canonical().0, *t))
+                .collect();
+            entries.sort_unstable();
+            entries.dedup();
+            assert_eq!(entries.len(), book.len());
+        }
+        assert!(OpeningBook::builtin(7).is_none());
+    }
+
+    #[test]
+    fn builtin_books_are_read_once() {
+        assert!(Arc::ptr_eq(&OpeningBook::builtin(10).unwrap(), &OpeningBook::builtin(10).unwrap()));
+    }
+
+    #[test]
+    fn chosen_moves_are_in_the_book() {
+        let board = Amazons::new_8x8().curr_board().clone();
+        let book = OpeningBook::builtin(board.board_size()).unwrap();
+        let moves: Vec<Move> = book.moves(&board, Team::Red).into_iter().map(|m| m.mv).collect();
+        assert!(!moves.is_empty());
+        for _ in 0..50 {
+            let next = book.choose(&board, Team::Red).unwrap();
+            assert!(moves.contains(&board.diff_move(&next).unwrap()));
+        }
+        let after = board.play(&moves[0]).unwrap();
+        let out_of_book = after.successors(Team::Blue).find(|b| !book.contains(b, Team::Red)).unwrap();
+        assert_eq!(book.choose(&out_of_book, Team::Red), None);
+    }
+}
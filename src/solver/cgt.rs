@@ -0,0 +1,571 @@
+use super::board::*;
+use super::endgame::*;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::time::Instant;
+
+/// Most squares, counting those with pieces, a region both teams can
+/// reach may have for its value to be worked out. The work grows
+/// about fivefold with each square more.
+pub const MAX_CGT_SQUARES: usize = 10;
+
+/// Most games a `RegionValues` makes before it gives up on a position.
+pub const MAX_GAMES: usize = 1 << 20;
+
+/// A game made by a `Games`, which compares equal to another from the
+/// same `Games` exactly when the two have the same value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Game(u32);
+
+/// A number `num / 2^exp`, as every number that is a short game is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dyadic {
+    pub num: i64,
+    pub exp: u32,
+}
+
+impl Dyadic {
+    pub fn new(num: i64, exp: u32) -> Dyadic {
+        let shift = num.trailing_zeros().min(exp);
+        Dyadic { num: num >> shift, exp: exp - shift }
+    }
+
+    pub fn integer(n: i64) -> Dyadic {
+        Dyadic { num: n, exp: 0 }
+    }
+
+    fn add(self, other: Dyadic) -> Dyadic {
+        let exp = self.exp.max(other.exp);
+        Dyadic::new((self.num << (exp - self.exp)) + (other.num << (exp - other.exp)), exp)
+    }
+
+    fn half(self) -> Dyadic {
+        Dyadic::new(self.num, self.exp + 1)
+    }
+
+    fn neg(self) -> Dyadic {
+        Dyadic { num: -self.num, exp: self.exp }
+    }
+}
+
+impl PartialOrd for Dyadic {
+    fn partial_cmp(&self, other: &Dyadic) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Dyadic {
+    fn cmp(&self, other: &Dyadic) -> Ordering {
+        let exp = self.exp.max(other.exp);
+        (self.num << (exp - self.exp)).cmp(&(other.num << (exp - other.exp)))
+    }
+}
+
+impl fmt::Display for Dyadic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.exp {
+            0 => write!(f, "{}", self.num),
+            exp => write!(f, "{}/{}", self.num, 1u64 << exp),
+        }
+    }
+}
+
+/// Short partizan games under the rule that a player left without a
+/// move loses, kept in canonical form so that games with the same value
+/// are the same `Game`.
+///
+/// Left and Right are the two players; a game is greater than zero if
+/// Left wins it whoever moves first.
+#[derive(Clone, Debug)]
+pub struct Games {
+    /// Left and right options of each game, sorted.
+    options: Vec<(Vec<Game>, Vec<Game>)>,
+    /// Each game by its options, so that no game is stored twice.
+    forms: HashMap<(Vec<Game>, Vec<Game>), Game>,
+    le: HashMap<(Game, Game), bool>,
+    sums: HashMap<(Game, Game), Game>,
+}
+
+impl Default for Games {
+    fn default() -> Games {
+        let mut games = Games { options: Vec::new(), forms: HashMap::new(), le: HashMap::new(), sums: HashMap::new() };
+        games.form(Vec::new(), Vec::new());
+        games
+    }
+}
+
+impl Games {
+    pub fn new() -> Games {
+        Games::default()
+    }
+
+    /// Number of distinct games made so far.
+    pub fn len(&self) -> usize {
+        self.options.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty()
+    }
+
+    /// The game with no moves for either player.
+    pub fn zero(&self) -> Game {
+        Game(0)
+    }
+
+    pub fn left(&self, g: Game) -> &[Game] {
+        &self.options[g.0 as usize].0
+    }
+
+    pub fn right(&self, g: Game) -> &[Game] {
+        &self.options[g.0 as usize].1
+    }
+
+    /// The game `{left | right}`.
+    pub fn game(&mut self, mut left: Vec<Game>, mut right: Vec<Game>) -> Game {
+        loop {
+            left = self.undominated(left, true);
+            right = self.undominated(right, false);
+            let g = self.form(left.clone(), right.clone());
+            // An option is reversible if the other player has an answer
+            // to it at least as good for them as `g` itself, and can be
+            // replaced by the moves from that answer.
+            let mut reversed = false;
+            let mut new_left = Vec::new();
+            for a in left {
+                match self.right(a).to_vec().into_iter().find(|&ar| self.le(ar, g)) {
+                    Some(ar) => {
+                        new_left.extend_from_slice(self.left(ar));
+                        reversed = true;
+                    },
+                    None => new_left.push(a),
+                }
+            }
+            let mut new_right = Vec::new();
+            for b in right {
+                match self.left(b).to_vec().into_iter().find(|&bl| self.le(g, bl)) {
+                    Some(bl) => {
+                        new_right.extend_from_slice(self.right(bl));
+                        reversed = true;
+                    },
+                    None => new_right.push(b),
+                }
+            }
+            if !reversed {
+                return g;
+            }
+            left = new_left;
+            right = new_right;
+        }
+    }
+
+    /// The integer `n`.
+    pub fn integer(&mut self, n: i64) -> Game {
+        match n.cmp(&0) {
+            Ordering::Equal => self.zero(),
+            Ordering::Greater => {
+                let less = self.integer(n - 1);
+                self.game(vec![less], Vec::new())
+            },
+            Ordering::Less => {
+                let more = self.integer(n + 1);
+                self.game(Vec::new(), vec![more])
+            },
+        }
+    }
+
+    /// Whether `g` is at most `h`: Right wins `g - h` if Left moves first.
+    pub fn le(&mut self, g: Game, h: Game) -> bool {
+        if g == h {
+            return true;
+        }
+        if let Some(&le) = self.le.get(&(g, h)) {
+            return le;
+        }
+        let le = !self.left(g).to_vec().into_iter().any(|gl| self.le(h, gl))
+            && !self.right(h).to_vec().into_iter().any(|hr| self.le(hr, g));
+        self.le.insert((g, h), le);
+        le
+    }
+
+    /// The sum of two games, in which each player moves in either one.
+    pub fn add(&mut self, g: Game, h: Game) -> Game {
+        if g == self.zero() {
+            return h;
+        }
+        if h == self.zero() {
+            return g;
+        }
+        let key = (g.min(h), g.max(h));
+        if let Some(&sum) = self.sums.get(&key) {
+            return sum;
+        }
+        let mut left = Vec::new();
+        for gl in self.left(g).to_vec() {
+            left.push(self.add(gl, h));
+        }
+        for hl in self.left(h).to_vec() {
+            left.push(self.add(g, hl));
+        }
+        let mut right = Vec::new();
+        for gr in self.right(g).to_vec() {
+            right.push(self.add(gr, h));
+        }
+        for hr in self.right(h).to_vec() {
+            right.push(self.add(g, hr));
+        }
+        let sum = self.game(left, right);
+        self.sums.insert(key, sum);
+        sum
+    }
+
+    /// The game with the players' roles swapped.
+    pub fn neg(&mut self, g: Game) -> Game {
+        let left = self.right(g).to_vec().into_iter().map(|gr| self.neg(gr)).collect();
+        let right = self.left(g).to_vec().into_iter().map(|gl| self.neg(gl)).collect();
+        self.game(left, right)
+    }
+
+    /// Whether Left wins `g` moving first.
+    pub fn left_wins_first(&mut self, g: Game) -> bool {
+        !self.le(g, self.zero())
+    }
+
+    /// Whether Right wins `g` moving first.
+    pub fn right_wins_first(&mut self, g: Game) -> bool {
+        !self.le(self.zero(), g)
+    }
+
+    /// The value of `g` if it is a number.
+    pub fn number(&self, g: Game) -> Option<Dyadic> {
+        match (self.left(g), self.right(g)) {
+            ([], []) => Some(Dyadic::integer(0)),
+            (&[l], []) => self.number(l).filter(|x| x.exp == 0 && x.num >= 0).map(|x| x.add(Dyadic::integer(1))),
+            ([], &[r]) => self.number(r).filter(|x| x.exp == 0 && x.num <= 0).map(|x| x.add(Dyadic::integer(-1))),
+            // In canonical form, a number between two others is halfway.
+            (&[l], &[r]) => match (self.number(l), self.number(r)) {
+                (Some(a), Some(b)) if a < b => Some(a.add(b).half()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// `n` if `g` is the nimber `*n`, whose options for both players
+    /// are `*0` up to `*(n-1)`.
+    pub fn nimber(&self, g: Game) -> Option<u32> {
+        let (left, right) = (self.left(g), self.right(g));
+        if left != right {
+            return None;
+        }
+        let mut values = left.iter().map(|&o| self.nimber(o)).collect::<Option<Vec<u32>>>()?;
+        values.sort_unstable();
+        match values.iter().enumerate().all(|(i, &v)| v == i as u32) {
+            true => Some(values.len() as u32),
+            false => None,
+        }
+    }
+
+    /// The usual name of `g`: a number like `3/4`, a nimber like `*2`,
+    /// `1*`, `↑`, `↓`, `±1`, or else its options as `{1|*, 0}`.
+    pub fn name(&self, g: Game) -> String {
+        if let Some(x) = self.number(g) {
+            return x.to_string();
+        }
+        match self.nimber(g) {
+            Some(1) => return String::from("*"),
+            Some(n) => return format!("*{}", n),
+            None => {},
+        }
+        let (left, right) = (self.left(g), self.right(g));
+        let star = |o: Game| self.nimber(o) == Some(1);
+        match (left, right) {
+            (&[l], &[r]) if l == r && self.number(l).is_some() => return format!("{}*", self.name(l)),
+            (&[l], &[r]) if l == self.zero() && star(r) => return String::from("↑"),
+            (&[l], &[r]) if star(l) && r == self.zero() => return String::from("↓"),
+            (&[a, b], &[r]) if r == self.zero() && (a == r && star(b) || b == r && star(a)) => return String::from("↑*"),
+            (&[l], &[a, b]) if l == self.zero() && (a == l && star(b) || b == l && star(a)) => return String::from("↓*"),
+            (&[l], &[r]) => match (self.number(l), self.number(r)) {
+                (Some(a), Some(b)) if a == b.neg() => return format!("±{}", a),
+                _ => {},
+            },
+            _ => {},
+        }
+        let names = |options: &[Game]| options.iter().map(|&o| self.name(o)).collect::<Vec<String>>().join(", ");
+        format!("{{{}|{}}}", names(left), names(right))
+    }
+
+    /// Options with those no better for their player than another
+    /// removed, sorted.
+    fn undominated(&mut self, mut options: Vec<Game>, left: bool) -> Vec<Game> {
+        options.sort_unstable();
+        options.dedup();
+        let all = options.clone();
+        options.retain(|&o| !all.iter().any(|&p| p != o && if left { self.le(o, p) } else { self.le(p, o) }));
+        options
+    }
+
+    /// The game with exactly these options, which must be sorted.
+    fn form(&mut self, left: Vec<Game>, right: Vec<Game>) -> Game {
+        if let Some(&g) = self.forms.get(&(left.clone(), right.clone())) {
+            return g;
+        }
+        let g = Game(self.options.len() as u32);
+        self.options.push((left.clone(), right.clone()));
+        self.forms.insert((left, right), g);
+        g
+    }
+}
+
+/// The game values of positions, as sums of the values of their
+/// components, with Red playing Left.
+///
+/// Components are kept by shape, so that one that is a rotation or
+/// reflection of another is worked out once. Everything is kept until
+/// the `RegionValues` is dropped, so each search makes its own, and
+/// gives up once it has made `MAX_GAMES` games.
+#[derive(Clone, Debug, Default)]
+pub struct RegionValues {
+    games: Games,
+    values: HashMap<Vec<u8>, Game>,
+    /// The moves in components only one team reaches.
+    regions: RegionTable,
+    /// Set from another thread to give up.
+    stop: Option<Arc<AtomicBool>>,
+    /// When to give up.
+    deadline: Option<Instant>,
+}
+
+impl RegionValues {
+    pub fn new() -> RegionValues {
+        RegionValues::default()
+    }
+
+    /// Values that give up, as if too big to work out, once `stop` is set
+    /// or `deadline` passes.
+    pub fn with_limits(stop: Arc<AtomicBool>, deadline: Option<Instant>) -> RegionValues {
+        RegionValues { stop: Some(stop), deadline, ..RegionValues::default() }
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop.as_ref().is_some_and(|s| s.load(AtomicOrdering::Relaxed))
+            || self.deadline.is_some_and(|d| Instant::now() >= d)
+            || self.games.len() >= MAX_GAMES
+    }
+
+    pub fn games(&self) -> &Games {
+        &self.games
+    }
+
+    /// The components of `board` with pieces and their values, or None
+    /// if one is too big to work out: one both teams reach with more than
+    /// `MAX_CGT_SQUARES` squares, or one only one team reaches with more
+    /// than `MAX_REGION_SQUARES` empty squares.
+    pub fn components(&mut self, board: &Board) -> Option<Vec<(Component, Game)>> {
+        let components: Vec<Component> = components(board).into_iter()
+            .filter(|c| !c.pieces.is_empty())
+            .collect();
+        let too_big = |c: &Component| match contested(c) {
+            true => c.squares.len() + c.pieces.len() > MAX_CGT_SQUARES,
+            false => c.squares.len() > MAX_REGION_SQUARES,
+        };
+        if components.iter().any(too_big) {
+            return None;
+        }
+        components.into_iter()
+            .map(|c| {
+                let value = self.component_value(&c)?;
+                Some((c, value))
+            })
+            .collect()
+    }
+
+    /// The value of `board`, the sum of the values of its components.
+    pub fn value(&mut self, board: &Board) -> Option<Game> {
+        let mut sum = self.games.zero();
+        for (_, value) in self.components(board)? {
+            sum = self.games.add(sum, value);
+        }
+        Some(sum)
+    }
+
+    /// Whether `team` wins from `board` if it is to move.
+    pub fn wins(&mut self, board: &Board, team: Team) -> Option<bool> {
+        let value = self.value(board)?;
+        Some(match team {
+            Team::Red => self.games.left_wins_first(value),
+            Team::Blue => self.games.right_wins_first(value),
+        })
+    }
+
+    /// The position after a winning move for `team` from `board`, if it
+    /// has one.
+    pub fn winning_move(&mut self, board: &Board, team: Team) -> Option<Board> {
+        if !self.wins(board, team)? {
+            return None;
+        }
+        for next in board.successors(team) {
+            match self.wins(&next, team.other()) {
+                Some(false) => return Some(next),
+                None if self.stopped() => return None,
+                _ => {},
+            }
+        }
+        None
+    }
+
+    fn component_value(&mut self, component: &Component) -> Option<Game> {
+        if self.stopped() {
+            return None;
+        }
+        let red = component.pieces.iter().all(|p| p.team == Team::Red);
+        let blue = component.pieces.iter().all(|p| p.team == Team::Blue);
+        if red || blue {
+            // Only one team moves here, so the value is its moves.
+            let region = IsolatedRegion {
+                team: component.pieces[0].team,
+                squares: component.squares.clone(),
+                pieces: component.pieces.iter().map(|p| p.pos).collect(),
+            };
+            let moves = self.regions.moves(&region)? as i64;
+            return Some(self.games.integer(if red { moves } else { -moves }));
+        }
+        let cells: Vec<(Pos, u8)> = component.squares.iter().map(|&p| (p, 0))
+            .chain(component.pieces.iter().map(|p| (p.pos, if p.team == Team::Red { 1 } else { 2 })))
+            .collect();
+        let key = shape_key(&cells);
+        if let Some(&value) = self.values.get(&key) {
+            return Some(value);
+        }
+        let board = component_board(component);
+        let left = board.successors(Team::Red).map(|b| self.value(&b)).collect::<Option<Vec<Game>>>()?;
+        let right = board.successors(Team::Blue).map(|b| self.value(&b)).collect::<Option<Vec<Game>>>()?;
+        let value = self.games.game(left, right);
+        self.values.insert(key, value);
+        Some(value)
+    }
+}
+
+/// Whether both teams have pieces in the component.
+fn contested(component: &Component) -> bool {
+    component.pieces.iter().any(|p| p.team != component.pieces[0].team)
+}
+
+/// A board just big enough for the component, with every other square
+/// filled.
+fn component_board(component: &Component) -> Board {
+    let cells: Vec<Pos> = component.squares.iter().cloned()
+        .chain(component.pieces.iter().map(|p| p.pos))
+        .collect();
+    let top = cells.iter().map(|p| p.row).min().unwrap_or(0);
+    let left = cells.iter().map(|p| p.col).min().unwrap_or(0);
+    let height = cells.iter().map(|p| p.row - top + 1).max().unwrap_or(0);
+    let width = cells.iter().map(|p| p.col - left + 1).max().unwrap_or(0);
+    let size = height.max(width) + 2;
+    let shift = |p: Pos| Pos { row: p.row - top + 1, col: p.col - left + 1 };
+    let players = component.pieces.iter().map(|p| Player { team: p.team, pos: shift(p.pos) }).collect();
+    let mut board = Board::new(size, players);
+    for row in 1..size - 1 {
+        for col in 1..size - 1 {
+            board.wall_set(Pos { row, col }, true);
+        }
+    }
+    for &p in &component.squares {
+        board.wall_set(shift(p), false);
+    }
+    board
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::Amazons;
+
+    #[test]
+    fn integers() {
+        let mut games = Games::new();
+        let three = games.integer(3);
+        let minus_two = games.integer(-2);
+        assert_eq!(games.name(three), "3");
+        assert_eq!(games.name(minus_two), "-2");
+        assert_eq!(games.number(three), Some(Dyadic::integer(3)));
+        let one = games.integer(1);
+        assert_eq!(games.add(three, minus_two), one);
+        // Dominated options are dropped: {0, 1 |} is 2.
+        let zero = games.zero();
+        let two = games.game(vec![zero, one], Vec::new());
+        assert_eq!(two, games.integer(2));
+    }
+
+    #[test]
+    fn star_is_a_first_player_win() {
+        let mut games = Games::new();
+        let zero = games.zero();
+        let star = games.game(vec![zero], vec![zero]);
+        assert_eq!(games.name(star), "*");
+        assert_eq!(games.nimber(star), Some(1));
+        assert!(games.left_wins_first(star) && games.right_wins_first(star));
+        assert_eq!(games.add(star, star), zero);
+    }
+
+    #[test]
+    fn up_is_positive_but_tiny() {
+        let mut games = Games::new();
+        let zero = games.zero();
+        let star = games.game(vec![zero], vec![zero]);
+        let up = games.game(vec![zero], vec![star]);
+        assert_eq!(games.name(up), "↑");
+        assert!(games.left_wins_first(up) && !games.right_wins_first(up));
+        let down = games.neg(up);
+        assert_eq!(games.name(down), "↓");
+        assert_eq!(games.add(up, down), zero);
+        let up_star = games.add(up, star);
+        assert_eq!(games.name(up_star), "↑*");
+        // Smaller than any positive number.
+        let one = games.integer(1);
+        let half = games.game(vec![zero], vec![one]);
+        let quarter = games.game(vec![zero], vec![half]);
+        assert!(games.le(up, quarter) && !games.le(quarter, up));
+    }
+
+    #[test]
+    fn switches_cancel() {
+        let mut games = Games::new();
+        let (one, minus_one) = (games.integer(1), games.integer(-1));
+        let pm_one = games.game(vec![one], vec![minus_one]);
+        assert_eq!(games.name(pm_one), "±1");
+        assert!(games.left_wins_first(pm_one) && games.right_wins_first(pm_one));
+        assert_eq!(games.add(pm_one, pm_one), games.zero());
+    }
+
+    #[test]
+    fn halves_add_up_to_one() {
+        let mut games = Games::new();
+        let (zero, one) = (games.zero(), games.integer(1));
+        let half = games.game(vec![zero], vec![one]);
+        assert_eq!(games.name(half), "1/2");
+        assert_eq!(games.number(half), Some(Dyadic::new(1, 1)));
+        assert_eq!(games.add(half, half), one);
+        let minus_half = games.neg(half);
+        let quarter = games.game(vec![zero], vec![half]);
+        let sum = games.add(half, minus_half);
+        assert_eq!(sum, zero);
+        let two_quarters = games.add(quarter, quarter);
+        assert_eq!(games.name(two_quarters), "1/2");
+    }
+
+    #[test]
+    fn stopped_values_give_up() {
+        let board = Amazons::from_fen("R1B/3/3 r").unwrap().curr_board().clone();
+        assert!(RegionValues::new().wins(&board, Team::Red).is_some());
+        let stopped = RegionValues::with_limits(Arc::new(AtomicBool::new(true)), None);
+        assert_eq!(stopped.clone().wins(&board, Team::Red), None);
+        assert_eq!(stopped.clone().winning_move(&board, Team::Red), None);
+        let late = RegionValues::with_limits(Arc::new(AtomicBool::new(false)), Some(Instant::now()));
+        assert_eq!(late.clone().wins(&board, Team::Red), None);
+    }
+}
@@ -0,0 +1,303 @@
+use super::board::*;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How much time each team gets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeControl {
+    /// One allowance for the whole game.
+    SuddenDeath(Duration),
+    /// An allowance, plus `increment` after every move.
+    Fischer { main: Duration, increment: Duration },
+    /// An allowance, then `periods` periods of `period` each. A move
+    /// made within a period keeps it; overrunning one uses it up.
+    ByoYomi { main: Duration, period: Duration, periods: u32 },
+}
+
+impl TimeControl {
+    /// Read `5:00` (sudden death), `5:00+5` (Fischer, five seconds a
+    /// move) or `5:00+3x30` (byo-yomi, three periods of thirty seconds).
+    /// Times are seconds or `m:ss`.
+    pub fn parse(s: &str) -> Result<TimeControl, String> {
+        let (main, extra) = match s.split_once('+') {
+            Some((main, extra)) => (parse_time(main)?, Some(extra)),
+            None => (parse_time(s)?, None),
+        };
+        match extra.map(|e| e.split_once('x')) {
+            None => Ok(TimeControl::SuddenDeath(main)),
+            Some(None) => Ok(TimeControl::Fischer { main, increment: parse_time(extra.unwrap())? }),
+            Some(Some((periods, period))) => Ok(TimeControl::ByoYomi {
+                main,
+                period: parse_time(period)?,
+                periods: periods.trim().parse().map_err(|_| format!("bad number of periods {:?}", periods))?,
+            }),
+        }
+    }
+
+    fn main(&self) -> Duration {
+        match *self {
+            TimeControl::SuddenDeath(main) => main,
+            TimeControl::Fischer { main, .. } => main,
+            TimeControl::ByoYomi { main, .. } => main,
+        }
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TimeControl::SuddenDeath(main) => write!(f, "{}", minutes(main)),
+            TimeControl::Fischer { main, increment } =>
+                write!(f, "{}+{}", minutes(main), increment.as_secs()),
+            TimeControl::ByoYomi { main, period, periods } =>
+                write!(f, "{}+{}x{}", minutes(main), periods, period.as_secs()),
+        }
+    }
+}
+
+fn parse_time(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let secs = match s.split_once(':') {
+        Some((m, sec)) => m.parse::<u64>().ok()
+            .zip(sec.parse::<u64>().ok().filter(|&sec| sec < 60))
+            .map(|(m, sec)| m * 60 + sec),
+        None => s.parse::<u64>().ok(),
+    };
+    secs.map(Duration::from_secs).ok_or(format!("bad time {:?}, expected seconds or m:ss", s))
+}
+
+/// `m:ss`, with tenths under ten seconds.
+pub fn format_time(d: Duration) -> String {
+    if d < Duration::from_secs(10) {
+        format!("0:0{}.{}", d.as_secs(), d.subsec_millis() / 100)
+    } else {
+        minutes(d)
+    }
+}
+
+fn minutes(d: Duration) -> String {
+    format!("{}:{:02}", d.as_secs() / 60, d.as_secs() % 60)
+}
+
+/// Time left for one team.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Allowance {
+    main: Duration,
+    periods: u32,
+}
+
+/// A chess clock for both teams.
+///
+/// Only one side's time runs at once. `press` stops it after a move
+/// and starts the other side's.
+#[derive(Clone, Debug)]
+pub struct Clock {
+    control: TimeControl,
+    left: HashMap<Team, Allowance>,
+    /// The team whose time is running, and since when.
+    running: Option<(Team, Instant)>,
+    flagged: Option<Team>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Clock {
+        let periods = match control {
+            TimeControl::ByoYomi { periods, .. } => periods,
+            _ => 0,
+        };
+        let allowance = Allowance { main: control.main(), periods };
+        Clock {
+            control,
+            left: Team::teams().into_iter().map(|t| (t, allowance)).collect(),
+            running: None,
+            flagged: None,
+        }
+    }
+
+    pub fn control(&self) -> TimeControl {
+        self.control
+    }
+
+    /// Start the time of the team to move.
+    pub fn start(&mut self, team: Team) {
+        self.running = Some((team, Instant::now()));
+    }
+
+    /// The team whose time is running.
+    pub fn running(&self) -> Option<Team> {
+        self.running.map(|(t, _)| t)
+    }
+
+    /// Charge the running team for its move and start the other team's
+    /// time, unless the move came too late.
+    pub fn press(&mut self) {
+        let increment = match self.control {
+            TimeControl::Fischer { increment, .. } => increment,
+            _ => Duration::ZERO,
+        };
+        if let Some(team) = self.stop(increment) {
+            self.running = Some((team.other(), Instant::now()));
+        }
+    }
+
+    /// Charge the running team for the time it has used and start
+    /// `team`'s, as when moves are taken back. There is no increment,
+    /// since no move was made.
+    pub fn switch(&mut self, team: Team) {
+        if self.running.is_none() || self.stop(Duration::ZERO).is_some() {
+            self.running = Some((team, Instant::now()));
+        }
+    }
+
+    /// Stop the running team's time, charging it and adding `increment`.
+    /// Returns the team, or None if nothing ran or its flag fell.
+    fn stop(&mut self, increment: Duration) -> Option<Team> {
+        let (team, since) = self.running.take()?;
+        match self.charge(team, since.elapsed()) {
+            Some((mut left, _)) => {
+                left.main += increment;
+                self.left.insert(team, left);
+                Some(team)
+            },
+            None => {
+                self.left.insert(team, Allowance { main: Duration::ZERO, periods: 0 });
+                self.flagged = Some(team);
+                None
+            },
+        }
+    }
+
+    /// The team that ran out of time, checking the running time too.
+    pub fn flagged(&self) -> Option<Team> {
+        self.flagged.or_else(|| match self.running {
+            Some((team, since)) if self.charge(team, since.elapsed()).is_none() => Some(team),
+            _ => None,
+        })
+    }
+
+    /// Time until the team's flag falls if it does not move: what is left
+    /// of the main time, or of the current byo-yomi period.
+    pub fn remaining(&self, team: Team) -> Duration {
+        match (self.charge(team, self.running_for(team)), self.control) {
+            (None, _) => Duration::ZERO,
+            (Some((left, used)), TimeControl::ByoYomi { period, .. }) if left.main.is_zero() =>
+                period - used,
+            (Some((left, _)), _) => left.main,
+        }
+    }
+
+    /// How long an AI should spend on its next move, given what it
+    /// has left. Assumes around twenty more moves to make.
+    pub fn budget(&self, team: Team) -> Duration {
+        let left = self.current(team);
+        match self.control {
+            TimeControl::SuddenDeath(_) => left.main / 20,
+            TimeControl::Fischer { increment, .. } =>
+                (left.main / 20 + increment * 4 / 5).min(left.main / 2),
+            TimeControl::ByoYomi { period, .. } if left.periods > 0 =>
+                left.main / 20 + period * 4 / 5,
+            TimeControl::ByoYomi { .. } => left.main / 20,
+        }
+    }
+
+    /// Like `remaining`, with the byo-yomi periods left, e.g. `0:25 (3x30)`.
+    pub fn describe(&self, team: Team) -> String {
+        let time = format_time(self.remaining(team));
+        match self.control {
+            TimeControl::ByoYomi { period, .. } => {
+                format!("{} ({}x{})", time, self.current(team).periods, period.as_secs())
+            },
+            _ => time,
+        }
+    }
+
+    fn running_for(&self, team: Team) -> Duration {
+        match self.running {
+            Some((t, since)) if t == team => since.elapsed(),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// The team's allowance as if it moved now.
+    fn current(&self, team: Team) -> Allowance {
+        self.charge(team, self.running_for(team))
+            .map_or(Allowance { main: Duration::ZERO, periods: 0 }, |(left, _)| left)
+    }
+
+    /// The allowance left after `team` spends `elapsed` on a move, with
+    /// the time taken from the byo-yomi period it ended in, or None if
+    /// that was too long.
+    fn charge(&self, team: Team, elapsed: Duration) -> Option<(Allowance, Duration)> {
+        let mut left = self.left[&team];
+        if elapsed <= left.main {
+            left.main -= elapsed;
+            return Some((left, Duration::ZERO));
+        }
+
+        let mut over = elapsed - left.main;
+        left.main = Duration::ZERO;
+        match self.control {
+            TimeControl::ByoYomi { period, .. } => {
+                while over > period && left.periods > 0 {
+                    over -= period;
+                    left.periods -= 1;
+                }
+                Some((left, over)).filter(|(left, _)| left.periods > 0)
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread::sleep;
+
+    const TICK: Duration = Duration::from_millis(30);
+
+    #[test]
+    fn time_controls_round_trip() {
+        for text in ["5:00", "5:00+5", "5:00+3x30"].iter() {
+            assert_eq!(TimeControl::parse(text).unwrap().to_string(), *text);
+        }
+        assert_eq!(TimeControl::parse("90").unwrap(), TimeControl::SuddenDeath(Duration::from_secs(90)));
+        assert!(TimeControl::parse("1:75").is_err());
+    }
+
+    #[test]
+    fn switching_charges_the_time_used() {
+        let main = Duration::from_secs(60);
+        let mut clock = Clock::new(TimeControl::Fischer { main, increment: Duration::from_secs(5) });
+        clock.start(Team::Red);
+        sleep(TICK);
+        clock.switch(Team::Blue);
+        assert_eq!(clock.running(), Some(Team::Blue));
+        assert!(clock.remaining(Team::Red) <= main - TICK);
+        assert!(clock.remaining(Team::Blue) > main - TICK);
+    }
+
+    #[test]
+    fn pressing_adds_the_increment() {
+        let main = Duration::from_secs(60);
+        let mut clock = Clock::new(TimeControl::Fischer { main, increment: Duration::from_secs(5) });
+        clock.start(Team::Red);
+        clock.press();
+        assert_eq!(clock.running(), Some(Team::Blue));
+        assert!(clock.remaining(Team::Red) > main);
+    }
+
+    #[test]
+    fn overrunning_sudden_death_flags() {
+        let mut clock = Clock::new(TimeControl::SuddenDeath(Duration::from_millis(10)));
+        clock.start(Team::Red);
+        sleep(TICK);
+        assert_eq!(clock.flagged(), Some(Team::Red));
+        clock.switch(Team::Blue);
+        assert_eq!(clock.running(), None);
+        assert_eq!(clock.flagged(), Some(Team::Red));
+    }
+}
@@ -0,0 +1,587 @@
+use super::bitboard::*;
+use super::board::*;
+use super::{Amazons, EvalStrategy};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::LazyLock;
+
+/// Most empty squares a region may have for its moves to be counted.
+pub const MAX_REGION_SQUARES: usize = 12;
+
+/// The region database shipped with the game, built by `--build-regions`.
+const REGIONS: &str = include_str!("../../data/regions.txt");
+
+/// The shipped region database, read the first time a region is counted.
+static BUILTIN_REGIONS: LazyLock<RegionDatabase> =
+    LazyLock::new(|| RegionDatabase::parse(REGIONS).expect("the shipped region database is valid"));
+
+/// Most entries a `RegionTable` keeps of each kind before it starts over.
+const MAX_REGION_ENTRIES: usize = 1 << 16;
+
+/// A part of the board that only one team's pieces can reach.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IsolatedRegion {
+    pub team: Team,
+    /// The empty squares in the region.
+    pub squares: Vec<Pos>,
+    /// The team's pieces in or next to the region.
+    pub pieces: Vec<Pos>,
+}
+
+/// Squares connected by king steps through empty squares and pieces,
+/// so that pieces touching two areas join them, and so do pieces next
+/// to each other, since either may move to let the other through.
+/// Moves in one component never change another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Component {
+    /// The empty squares in the component.
+    pub squares: Vec<Pos>,
+    pub pieces: Vec<Player>,
+}
+
+/// The components of `board` with any empty squares or pieces.
+pub fn components(board: &Board) -> Vec<Component> {
+    let layout = board.layout();
+    component_masks(board)
+        .map(|(squares, pieces)| Component {
+            squares: layout.squares(squares).collect(),
+            pieces: board.players().filter(|p| pieces & layout.bit(p.pos) != 0).cloned().collect(),
+        })
+        .collect()
+}
+
+/// The components of `board` as masks of their empty squares and of
+/// their pieces, without building lists of squares.
+pub fn component_masks(board: &Board) -> ComponentMasks {
+    let empty = board.empty();
+    let open = empty | board.pieces(Team::Red) | board.pieces(Team::Blue);
+    ComponentMasks { layout: board.layout(), empty, open, left: open }
+}
+
+/// The iterator of `component_masks`.
+pub struct ComponentMasks {
+    layout: &'static Layout,
+    empty: u128,
+    open: u128,
+    left: u128,
+}
+
+impl Iterator for ComponentMasks {
+    type Item = (u128, u128);
+
+    fn next(&mut self) -> Option<(u128, u128)> {
+        if self.left == 0 {
+            return None;
+        }
+        let mut joined = self.left & self.left.wrapping_neg();
+        loop {
+            let grown = joined | self.layout.neighbours(joined) & self.open;
+            if grown == joined {
+                break;
+            }
+            joined = grown;
+        }
+        self.left &= !joined;
+        Some((joined & self.empty, joined & !self.empty))
+    }
+}
+
+/// The components of `board` holding pieces of one team only.
+pub fn isolated_regions(board: &Board) -> Vec<IsolatedRegion> {
+    components(board).into_iter()
+        .filter_map(|c| {
+            let team = c.pieces.first()?.team;
+            if c.pieces.iter().any(|p| p.team != team) {
+                return None;
+            }
+            Some(IsolatedRegion { team, squares: c.squares, pieces: c.pieces.iter().map(|p| p.pos).collect() })
+        })
+        .collect()
+}
+
+/// The most moves a team can make in each region only it can reach, by
+/// the shape of the region and where the pieces stand in it.
+///
+/// Shapes that are rotations or reflections of each other share an
+/// entry. Shapes in the shipped `RegionDatabase` are looked up there;
+/// others are counted by exhaustive search the first time they are
+/// asked for. A table belongs to one search or solver, and forgets what
+/// it counted once it holds `MAX_REGION_ENTRIES` of either kind.
+#[derive(Clone, Debug, Default)]
+pub struct RegionTable {
+    moves: HashMap<Vec<u8>, u32>,
+    /// The same counts by the board size and the masks of the region's
+    /// squares and pieces, which are quicker to look up than its shape.
+    placed: HashMap<(i8, u128, u128), u32>,
+}
+
+impl RegionTable {
+    pub fn new() -> RegionTable {
+        RegionTable::default()
+    }
+
+    /// Number of shapes counted so far.
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    /// The longest sequence of moves the pieces of `region` can make in
+    /// it, or None if it has more than `MAX_REGION_SQUARES` squares.
+    pub fn moves(&mut self, region: &IsolatedRegion) -> Option<u32> {
+        if region.squares.len() > MAX_REGION_SQUARES {
+            return None;
+        }
+        // A piece can always step onto a lone square and shoot back.
+        if region.squares.len() <= 1 {
+            return Some(region.squares.len() as u32);
+        }
+        let cells: Vec<(Pos, u8)> = region.squares.iter().map(|&p| (p, 0))
+            .chain(region.pieces.iter().map(|&p| (p, 1)))
+            .collect();
+        let key = shape_key(&cells);
+        if let Some(n) = BUILTIN_REGIONS.get(&key).or_else(|| self.moves.get(&key).cloned()) {
+            return Some(n);
+        }
+        let n = Solitaire::new(region).most_moves();
+        if self.moves.len() >= MAX_REGION_ENTRIES {
+            self.moves.clear();
+        }
+        self.moves.insert(key, n);
+        Some(n)
+    }
+
+    /// `moves` for the region of `team` with the empty `squares` and the
+    /// `pieces` of a board of `layout`.
+    pub fn moves_in(&mut self, layout: &'static Layout, team: Team, squares: u128, pieces: u128) -> Option<u32> {
+        if squares.count_ones() as usize > MAX_REGION_SQUARES {
+            return None;
+        }
+        let placed = (layout.n, squares, pieces);
+        if let Some(&n) = self.placed.get(&placed) {
+            return Some(n);
+        }
+        let region = IsolatedRegion {
+            team,
+            squares: layout.squares(squares).collect(),
+            pieces: layout.squares(pieces).collect(),
+        };
+        let n = self.moves(&region)?;
+        if self.placed.len() >= MAX_REGION_ENTRIES {
+            self.placed.clear();
+        }
+        self.placed.insert(placed, n);
+        Some(n)
+    }
+}
+
+/// Move counts of region shapes worked out ahead of time, for the
+/// shapes that come up in games so often that counting them again in
+/// every search would be a waste.
+#[derive(Clone, Debug, Default)]
+pub struct RegionDatabase {
+    moves: HashMap<Vec<u8>, u32>,
+}
+
+impl RegionDatabase {
+    pub fn new() -> RegionDatabase {
+        RegionDatabase::default()
+    }
+
+    /// The database shipped with the game.
+    pub fn builtin() -> &'static RegionDatabase {
+        &BUILTIN_REGIONS
+    }
+
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    /// The moves of the region with the `shape_key` `key`, if known.
+    pub fn get(&self, key: &[u8]) -> Option<u32> {
+        self.moves.get(key).cloned()
+    }
+
+    /// Count the moves of `region` unless it is known already.
+    pub fn insert(&mut self, region: &IsolatedRegion) {
+        let cells: Vec<(Pos, u8)> = region.squares.iter().map(|&p| (p, 0))
+            .chain(region.pieces.iter().map(|&p| (p, 1)))
+            .collect();
+        let key = shape_key(&cells);
+        self.moves.entry(key).or_insert_with(|| Solitaire::new(region).most_moves());
+    }
+
+    /// Read a database written by the `Display` impl.
+    ///
+    /// Each line is a picture of a region, its rows separated by `/`,
+    /// with `.` for its empty squares, `A` for the pieces and `#` for
+    /// squares outside it, then the most moves the pieces can make:
+    ///
+    /// `A../#.. 3`
+    ///
+    /// Lines starting with `//` are comments.
+    pub fn parse(text: &str) -> Result<RegionDatabase, String> {
+        let mut database = RegionDatabase::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let err = |msg: String| format!("line {}: {}", i + 1, msg);
+            let (picture, moves) = line.split_once(' ')
+                .ok_or_else(|| err(format!("expected a region and its moves, not {:?}", line)))?;
+            let moves = moves.parse().map_err(|_| err(format!("bad move count {:?}", moves)))?;
+            let mut cells = Vec::new();
+            for (row, rank) in picture.split('/').enumerate() {
+                for (col, c) in rank.chars().enumerate() {
+                    let pos = Pos { row: row as i8 + 1, col: col as i8 + 1 };
+                    match c {
+                        '.' => cells.push((pos, 0)),
+                        'A' => cells.push((pos, 1)),
+                        '#' => {}
+                        _ => return Err(err(format!("unexpected {:?} in {:?}", c, picture))),
+                    }
+                }
+            }
+            if cells.iter().filter(|c| c.1 == 0).count() > MAX_REGION_SQUARES {
+                return Err(err(format!("{:?} has more than {} squares", picture, MAX_REGION_SQUARES)));
+            }
+            database.moves.insert(shape_key(&cells), moves);
+        }
+        Ok(database)
+    }
+
+    /// Build a database of the regions met evaluating every move of
+    /// `games` games from each standard start, each move picked at
+    /// random from the `width` that score best. `progress` is told of
+    /// each game after it is played.
+    pub fn build(games: usize, width: usize, progress: &mut dyn FnMut(&Board, usize)) -> RegionDatabase {
+        let mut database = RegionDatabase::new();
+        let mut regions = RegionTable::new();
+        // Fixed, so that building twice gives the same database.
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        for start in [Amazons::new_5x5(), Amazons::new_8x8(), Amazons::new_10x10()] {
+            for _ in 0..games {
+                let (mut board, mut team) = (start.curr_board().clone(), start.to_move());
+                loop {
+                    let mut next: Vec<(i64, Board)> = board.successors(team)
+                        .map(|b| (b.evaluate(team, EvalStrategy::QueenDistance, &mut regions), b))
+                        .collect();
+                    if next.is_empty() {
+                        break;
+                    }
+                    for (_, after) in &next {
+                        for region in isolated_regions(after) {
+                            if (2..=MAX_REGION_SQUARES).contains(&region.squares.len()) {
+                                database.insert(&region);
+                            }
+                        }
+                    }
+                    next.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    board = next.swap_remove((seed % next.len().min(width) as u64) as usize).1;
+                    team = team.other();
+                }
+                progress(start.curr_board(), database.len());
+            }
+        }
+        database
+    }
+}
+
+impl fmt::Display for RegionDatabase {
+    /// One line per shape as `parse` reads it, sorted so that databases
+    /// built alike compare alike.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines: Vec<String> = self.moves.iter()
+            .map(|(key, moves)| {
+                let cells: Vec<&[u8]> = key.chunks(3).collect();
+                let rows = cells.iter().map(|c| c[0]).max().unwrap_or(0) as usize + 1;
+                let cols = cells.iter().map(|c| c[1]).max().unwrap_or(0) as usize + 1;
+                let mut picture = vec![vec!['#'; cols]; rows];
+                for c in cells {
+                    picture[c[0] as usize][c[1] as usize] = if c[2] == 0 { '.' } else { 'A' };
+                }
+                let ranks: Vec<String> = picture.into_iter().map(|r| r.into_iter().collect()).collect();
+                format!("{} {}", ranks.join("/"), moves)
+            })
+            .collect();
+        lines.sort();
+        for line in lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Squares, each with a number for what is on it, under whichever
+/// symmetry lists them first, moved to the corner, as row, column and
+/// number. Regions that are rotations or reflections of each other
+/// have the same key.
+pub fn shape_key(cells: &[(Pos, u8)]) -> Vec<u8> {
+    Symmetry::ALL.iter()
+        .map(|&sym| {
+            // Any size big enough keeps every coordinate positive.
+            let mut moved: Vec<(i8, i8, u8)> = cells.iter()
+                .map(|&(p, what)| {
+                    let t = p.transform(sym, i8::MAX);
+                    (t.row, t.col, what)
+                })
+                .collect();
+            let top = moved.iter().map(|c| c.0).min().unwrap_or(0);
+            let left = moved.iter().map(|c| c.1).min().unwrap_or(0);
+            moved.sort_unstable();
+            moved.iter()
+                .flat_map(|&(row, col, what)| vec![(row - top) as u8, (col - left) as u8, what])
+                .collect::<Vec<u8>>()
+        })
+        .min()
+        .unwrap()
+}
+
+/// One team moving alone in a region, with the region's squares
+/// numbered as bits.
+struct Solitaire {
+    /// For each square, the squares along each direction out of it
+    /// while they stay in the region.
+    rays: Vec<Vec<Vec<usize>>>,
+    pieces: u32,
+    filled: u32,
+    best: HashMap<(u32, u32), u32>,
+}
+
+impl Solitaire {
+    fn new(region: &IsolatedRegion) -> Solitaire {
+        let cells: Vec<Pos> = region.pieces.iter().chain(&region.squares).cloned().collect();
+        let index = |p: Pos| cells.iter().position(|&c| c == p);
+        let rays = cells.iter()
+            .map(|&from| QUEEN_DIRS.iter()
+                .map(|&dir| (1..).map(|dist| index(from.with_offset(dir, dist)))
+                    .take_while(Option::is_some)
+                    .flatten()
+                    .collect())
+                .collect())
+            .collect();
+        let pieces = (1 << region.pieces.len()) - 1;
+        Solitaire { rays, pieces, filled: pieces, best: HashMap::new() }
+    }
+
+    fn most_moves(&mut self) -> u32 {
+        self.search(self.pieces, self.filled)
+    }
+
+    fn search(&mut self, pieces: u32, filled: u32) -> u32 {
+        if let Some(&n) = self.best.get(&(pieces, filled)) {
+            return n;
+        }
+        // Every move fills a square, so there can be no more moves than
+        // empty squares.
+        let bound = self.rays.len() as u32 - filled.count_ones();
+        let mut most = 0;
+        let mut left = pieces;
+        'pieces: while left != 0 {
+            let from = left.trailing_zeros() as usize;
+            left &= left - 1;
+            for dir in 0..8 {
+                for to in self.ray(from, dir, filled) {
+                    let moved = filled ^ (1 << from) ^ (1 << to);
+                    for shot_dir in 0..8 {
+                        for shot in self.ray(to, shot_dir, moved) {
+                            let n = 1 + self.search(pieces ^ (1 << from) ^ (1 << to), moved | 1 << shot);
+                            most = most.max(n);
+                            if most == bound {
+                                break 'pieces;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        self.best.insert((pieces, filled), most);
+        most
+    }
+
+    /// The empty squares along a direction from `from`, up to the first
+    /// filled one.
+    fn ray(&self, from: usize, dir: usize, filled: u32) -> Vec<usize> {
+        self.rays[from][dir].iter().cloned().take_while(|&to| filled & 1 << to == 0).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::Amazons;
+    use crate::solver::cgt::RegionValues;
+    use crate::solver::solve::Solver;
+
+    fn board(fen: &str) -> Board {
+        Amazons::from_fen(fen).unwrap().curr_board().clone()
+    }
+
+    #[test]
+    fn adjacent_pieces_join_their_components() {
+        // The pieces at d4 and d3 touch, so the square either one
+        // leaves lets the other through.
+        let components = components(&board("3#1/###R#/2#B#/1###1/2B1R r"));
+        let joined = components.iter()
+            .find(|c| c.pieces.iter().any(|p| p.pos == Pos { row: 4, col: 4 }))
+            .unwrap();
+        assert!(joined.pieces.iter().any(|p| p.pos == Pos { row: 3, col: 4 }));
+        assert!(joined.squares.contains(&Pos { row: 5, col: 3 }));
+        assert!(joined.squares.contains(&Pos { row: 2, col: 5 }));
+    }
+
+    #[test]
+    fn components_cover_the_board_once() {
+        let board = board("3#1/###R#/2#B#/1###1/2B1R r");
+        let components = components(&board);
+        let squares: usize = components.iter().map(|c| c.squares.len()).sum();
+        let pieces: usize = components.iter().map(|c| c.pieces.len()).sum();
+        assert_eq!(squares as u32, board.empty().count_ones());
+        assert_eq!(pieces, board.players().count());
+    }
+
+    fn square(row: i8, col: i8) -> Pos {
+        Pos { row, col }
+    }
+
+    #[test]
+    fn region_moves_count_the_best_order() {
+        let mut table = RegionTable::new();
+        // From the end of a corridor, stepping first makes two moves.
+        let corridor = IsolatedRegion { team: Team::Red, squares: vec![square(2, 1), square(3, 1)], pieces: vec![square(1, 1)] };
+        assert_eq!(table.moves(&corridor), Some(2));
+        // The other end of the corridor, turned on its side, is the same shape.
+        let turned = IsolatedRegion { team: Team::Blue, squares: vec![square(5, 4), square(5, 3)], pieces: vec![square(5, 5)] };
+        assert_eq!(table.moves(&turned), Some(2));
+        // Corridors come up in every game, so neither is counted again.
+        assert!(RegionDatabase::builtin().get(&shape_key(&[(square(1, 1), 1), (square(1, 2), 0), (square(1, 3), 0)])).is_some());
+        assert_eq!(table.len(), 0);
+
+        let block = IsolatedRegion { team: Team::Red, squares: vec![square(1, 2), square(2, 1), square(2, 2)], pieces: vec![square(1, 1)] };
+        assert_eq!(table.moves(&block), Some(3));
+        let big = IsolatedRegion {
+            team: Team::Red,
+            squares: (1..=4).flat_map(|row| (1..=4).map(move |col| square(row, col))).skip(1).collect(),
+            pieces: vec![square(1, 1)],
+        };
+        assert_eq!(table.moves(&big), None);
+    }
+
+    #[test]
+    fn region_moves_by_masks_match_the_shape() {
+        // Red shut in the top left corner, Blue near the bottom left.
+        let board = board("1R###/2#2/###2/#B###/#2## r");
+        let layout = board.layout();
+        let mut table = RegionTable::new();
+        let mut counted = 0;
+        for (squares, pieces) in component_masks(&board).filter(|&(_, pieces)| pieces != 0) {
+            let team = match pieces & board.pieces(Team::Red) {
+                0 => Team::Blue,
+                _ => Team::Red,
+            };
+            let region = IsolatedRegion { team, squares: layout.squares(squares).collect(), pieces: layout.squares(pieces).collect() };
+            let by_shape = RegionTable::new().moves(&region);
+            assert_eq!(table.moves_in(layout, team, squares, pieces), by_shape);
+            assert_eq!(table.moves_in(layout, team, squares, pieces), by_shape);
+            counted += 1;
+        }
+        assert_eq!(counted, 2);
+    }
+
+    #[test]
+    fn shipped_regions_match_a_fresh_count() {
+        let database = RegionDatabase::builtin();
+        assert!(database.len() > 1000);
+        // Counting them all takes a while without optimizations.
+        let mut keys: Vec<&Vec<u8>> = database.moves.keys().collect();
+        keys.sort();
+        for key in keys.into_iter().step_by(16) {
+            let moves = database.moves[key];
+            let cell = |c: &[u8]| square(c[0] as i8 + 1, c[1] as i8 + 1);
+            let region = IsolatedRegion {
+                team: Team::Red,
+                squares: key.chunks(3).filter(|c| c[2] == 0).map(cell).collect(),
+                pieces: key.chunks(3).filter(|c| c[2] == 1).map(cell).collect(),
+            };
+            assert_eq!(Solitaire::new(&region).most_moves(), moves, "{:?}", region);
+        }
+    }
+
+    #[test]
+    fn region_database_reads_what_it_writes() {
+        let mut database = RegionDatabase::new();
+        database.insert(&IsolatedRegion { team: Team::Red, squares: vec![square(1, 2), square(2, 1), square(2, 3)], pieces: vec![square(1, 1)] });
+        database.insert(&IsolatedRegion { team: Team::Blue, squares: vec![square(3, 3)], pieces: vec![square(1, 1), square(2, 2)] });
+        let text = database.to_string();
+        assert_eq!(text, ".##/#A#/##A 1\n.A/#./.# 3\n");
+        assert_eq!(RegionDatabase::parse(&text).unwrap().moves, database.moves);
+
+        assert!(RegionDatabase::parse("A.. 2\n// comment\n\n.A. 1").is_ok());
+        assert!(RegionDatabase::parse("A.x 2").is_err());
+        assert!(RegionDatabase::parse("A.. two").is_err());
+        assert!(RegionDatabase::parse("A.............").is_err());
+        assert!(RegionDatabase::parse(&format!("A{} 12", ".".repeat(13))).is_err());
+    }
+
+    #[test]
+    fn shipped_regions_are_not_counted_again() {
+        let key = RegionDatabase::builtin().moves.keys().max_by_key(|k| k.len()).unwrap();
+        let cell = |c: &[u8]| square(c[0] as i8 + 1, c[1] as i8 + 1);
+        let region = IsolatedRegion {
+            team: Team::Blue,
+            squares: key.chunks(3).filter(|c| c[2] == 0).map(cell).collect(),
+            pieces: key.chunks(3).filter(|c| c[2] == 1).map(cell).collect(),
+        };
+        let mut table = RegionTable::new();
+        assert_eq!(table.moves(&region), RegionDatabase::builtin().get(key));
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn region_values_agree_with_the_solver() {
+        let mut values = RegionValues::new();
+        let mut solver = Solver::with_cache_bits(16);
+        let example = board("3#1/###R#/2#B#/1###1/2B1R r");
+        assert_ne!(values.wins(&example, Team::Red), Some(true));
+        assert_eq!(solver.solve(&example, Team::Red), Ok(false));
+
+        // Random games on 5x5, compared once few enough squares are left.
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut compared = 0;
+        for _ in 0..60 {
+            let mut amazons = Amazons::new_5x5();
+            loop {
+                let team = amazons.to_move();
+                let board = amazons.curr_board().clone();
+                let next: Vec<Board> = board.successors(team).collect();
+                if next.is_empty() {
+                    break;
+                }
+                if board.empty().count_ones() <= 11 {
+                    if let Some(wins) = values.wins(&board, team) {
+                        assert_eq!(Ok(wins), solver.solve(&board, team), "{}", board.to_fen(team));
+                        compared += 1;
+                    }
+                }
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                let mv = board.diff_move(&next[(seed % next.len() as u64) as usize]).unwrap();
+                amazons.player_move(team, mv.player.pos, mv.new_pos, mv.new_shot).unwrap();
+            }
+        }
+        assert!(compared > 100);
+    }
+}
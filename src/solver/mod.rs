@@ -1,14 +1,252 @@
+/// Post-game review of recorded games.
+pub mod analysis;
+/// Sets of squares as the bits of a `u128`.
+pub mod bitboard;
 pub mod board;
+/// Opening moves by position.
+pub mod book;
+/// Combinatorial game theory values of endgame regions.
+pub mod cgt;
+/// Per-team clocks under the usual time controls.
+pub mod clock;
+/// Exact move counts for regions only one team can reach.
+pub mod endgame;
+/// Standard algebraic Amazons notation.
+pub mod notation;
+/// Game records with header tags, as exchanged by Amazons sites.
+pub mod record;
+/// Perfect play on small boards.
+pub mod solve;
+/// Search results shared between searches.
+pub mod table;
+/// History with variations.
+pub mod tree;
 
 use board::*;
-use smallvec::SmallVec;
+use book::*;
+use cgt::*;
+use clock::*;
+use endgame::*;
+use solve::*;
+use table::*;
+use tree::*;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A candidate move from `Amazons::hints`.
+#[derive(Clone, Debug)]
+pub struct Hint {
+    pub mv: Move,
+    /// Search score for the team to move, higher is better.
+    pub score: i64,
+    /// Square ownership after the move.
+    pub territory: Territory,
+}
+
+/// What a search did, for tuning and curiosity.
+#[derive(Clone, Debug, Default)]
+pub struct SearchInfo {
+    /// Positions generated.
+    pub nodes: u64,
+    /// Calls to the evaluation heuristic.
+    pub evals: u64,
+    /// Positions found in the transposition table.
+    pub table_hits: u64,
+    /// Moves deep the last completed search went.
+    pub depth: i32,
+    pub elapsed: Duration,
+    /// Score of the chosen line for the team that searched.
+    pub score: i64,
+    /// The line the search expects, starting with its move.
+    pub pv: Vec<Move>,
+    /// Whether the move came from the opening book instead.
+    pub book: bool,
+    /// Whether the move was found by solving the position instead,
+    /// and so wins against any defence.
+    pub solved: bool,
+    /// Positions expanded and moves generated from them, by ply.
+    expanded: Vec<u64>,
+    generated: Vec<u64>,
+}
+
+impl SearchInfo {
+    pub fn nodes_per_second(&self) -> f64 {
+        self.nodes as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    /// Average number of moves from the positions expanded at each
+    /// ply, starting from the root.
+    pub fn branching(&self) -> Vec<f64> {
+        self.expanded.iter().zip(&self.generated)
+            .map(|(&e, &g)| g as f64 / e.max(1) as f64)
+            .collect()
+    }
+
+    /// Count a position expanded at `ply`, with `generated` moves.
+    fn visit(&mut self, ply: usize, generated: u64) {
+        if self.expanded.len() <= ply {
+            self.expanded.resize(ply + 1, 0);
+            self.generated.resize(ply + 1, 0);
+        }
+        self.expanded[ply] += 1;
+        self.generated[ply] += generated;
+    }
+}
+
+impl fmt::Display for SearchInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pv: Vec<String> = self.pv.iter().map(|m| m.to_string()).collect();
+        if self.book {
+            return write!(f, "book move {}", pv.join(" "));
+        }
+        if self.solved {
+            return write!(f, "winning move {}", pv.join(" "));
+        }
+        writeln!(f, "depth {}, {} nodes, {} evaluations, {} table hits in {:.2}s ({:.0} nodes/s), score {}",
+                 self.depth, self.nodes, self.evals, self.table_hits, self.elapsed.as_secs_f64(),
+                 self.nodes_per_second(), self.score)?;
+        let branching: Vec<String> = self.branching().iter().map(|b| format!("{:.1}", b)).collect();
+        writeln!(f, "branching by ply: {}", branching.join(" "))?;
+        write!(f, "principal variation: {}", pv.join(" "))
+    }
+}
+
+/// State shared by the positions of one search.
+#[derive(Default)]
+struct Search {
+    info: SearchInfo,
+    /// Set from another thread to abandon the search.
+    stop: Option<Arc<AtomicBool>>,
+    /// When to abandon the search, so that a depth that takes longer
+    /// than `deepening` expected does not overrun its budget.
+    deadline: Option<Instant>,
+    /// Where `deepening` reports each depth it completes.
+    progress: Option<Sender<SearchInfo>>,
+    table: Option<Arc<Mutex<TranspositionTable>>>,
+    /// Moves left in the isolated regions the evaluation meets.
+    regions: RegionTable,
+    /// The scored moves of the position being searched at each ply,
+    /// kept between positions so that their room is allocated once.
+    moves: Vec<Vec<(i64, Board)>>,
+}
+
+impl Search {
+    fn stopped(&self) -> bool {
+        self.stop.as_ref().is_some_and(|s| s.load(Ordering::Relaxed))
+            || self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+
+    /// An empty move list for a position at `ply`, to hand back with
+    /// `give_back_moves` once the position is searched.
+    fn take_moves(&mut self, ply: usize) -> Vec<(i64, Board)> {
+        let mut moves = self.moves.get_mut(ply).map(std::mem::take).unwrap_or_default();
+        moves.clear();
+        moves
+    }
+
+    fn give_back_moves(&mut self, ply: usize, moves: Vec<(i64, Board)>) {
+        if self.moves.len() <= ply {
+            self.moves.resize_with(ply + 1, Vec::new);
+        }
+        self.moves[ply] = moves;
+    }
+}
+
+/// An AI search running on its own thread, from `Amazons::start_search`.
+pub struct SearchHandle {
+    stop: Arc<AtomicBool>,
+    /// Reports from the search, None if it makes none.
+    progress: Option<Receiver<SearchInfo>>,
+    thread: JoinHandle<(Option<Board>, SearchInfo)>,
+    /// The ply and position searched.
+    from: (usize, Board),
+}
+
+/// What a finished search found, to play with `Amazons::play_search`.
+pub struct SearchResult {
+    /// The position after the search's move.
+    best: Option<Board>,
+    pub info: SearchInfo,
+    /// The ply and position searched.
+    from: (usize, Board),
+}
+
+impl SearchHandle {
+    /// Ask the search to finish with the best move it has so far.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// A report for each depth completed since the last call, with the
+    /// best line so far.
+    pub fn progress(&self) -> Vec<SearchInfo> {
+        self.progress.iter().flat_map(|p| p.try_iter()).collect()
+    }
+
+    /// Stop the search and wait for it, discarding its move.
+    pub fn cancel(self) -> SearchInfo {
+        self.stop();
+        self.join().info
+    }
+
+    /// Wait for the search to finish.
+    pub fn join(self) -> SearchResult {
+        let (best, info) = self.thread.join().expect("search thread panicked");
+        SearchResult { best, info, from: self.from }
+    }
+}
+
+/// A search for hints running on its own thread, from
+/// `Amazons::start_hints`.
+pub struct HintHandle {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Vec<Hint>>,
+}
+
+impl HintHandle {
+    /// Ask the search to finish with the ranking it has so far.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Wait for the search to finish.
+    pub fn join(self) -> Vec<Hint> {
+        self.thread.join().expect("hint thread panicked")
+    }
+}
 
 /// Data structures for amazon simulation,
 /// history-tracking, and AI.
 pub struct Amazons {
     board_size: i8,
-    boards: Vec<Board>,
+    tree: GameTree,
+    /// Boards taken back by `undo`, the most recent last.
+    redo: Vec<Board>,
+    clock: Option<Clock>,
+    book: Option<Arc<OpeningBook>>,
+    solved: Option<Arc<SolvedPositions>>,
+    table: Arc<Mutex<TranspositionTable>>,
+    /// The solver for small boards, made when a search first needs it
+    /// and kept for what its caches hold.
+    solver: Arc<Mutex<Option<Solver>>>,
     cache: DistState,
+    /// Moves left in isolated regions, for `evaluate`.
+    regions: RegionTable,
+    first: Team,
 }
 
 
@@ -24,8 +262,16 @@ impl Amazons {
 
         Amazons {
             board_size: board_size,
-            boards: vec![Board::new(board_size, players)],
+            tree: GameTree::new(Board::new(board_size, players)),
+            redo: Vec::new(),
+            clock: None,
+            book: OpeningBook::builtin(board_size),
+            solved: None,
+            table: Arc::new(Mutex::new(TranspositionTable::new())),
+            solver: Arc::new(Mutex::new(None)),
             cache: DistState::with_board_size(board_size),
+            regions: RegionTable::new(),
+            first: Team::Red,
         }
     }
 
@@ -40,35 +286,232 @@ impl Amazons {
 
         Amazons {
             board_size: board_size,
-            boards: vec![Board::new(board_size, players)],
+            tree: GameTree::new(Board::new(board_size, players)),
+            redo: Vec::new(),
+            clock: None,
+            book: OpeningBook::builtin(board_size),
+            solved: None,
+            table: Arc::new(Mutex::new(TranspositionTable::new())),
+            solver: Arc::new(Mutex::new(None)),
+            cache: DistState::with_board_size(board_size),
+            regions: RegionTable::new(),
+            first: Team::Red,
+        }
+    }
+
+    /// The standard tournament start, where the Red pieces are
+    /// on a4, d1, g1 and j4.
+    pub fn new_10x10() -> Amazons {
+        let board_size = 10 + 2;
+
+        let players = vec![
+            Player{ team:Team::Red, pos:Pos {row:  4, col:  1} },
+            Player{ team:Team::Red, pos:Pos {row:  1, col:  4} },
+            Player{ team:Team::Red, pos:Pos {row:  1, col:  7} },
+            Player{ team:Team::Red, pos:Pos {row:  4, col: 10} },
+            Player{ team:Team::Blue, pos:Pos {row:  7, col:  1} },
+            Player{ team:Team::Blue, pos:Pos {row: 10, col:  4} },
+            Player{ team:Team::Blue, pos:Pos {row: 10, col:  7} },
+            Player{ team:Team::Blue, pos:Pos {row:  7, col: 10} },
+        ];
+
+        Amazons::from_board(Board::new(board_size, players), Team::Red)
+    }
+
+    /// Start from an arbitrary position.
+    pub fn from_board(board: Board, to_move: Team) -> Amazons {
+        let board_size = board.board_size();
+        Amazons {
+            board_size,
+            tree: GameTree::new(board),
+            redo: Vec::new(),
+            clock: None,
+            book: OpeningBook::builtin(board_size),
+            solved: None,
+            table: Arc::new(Mutex::new(TranspositionTable::new())),
+            solver: Arc::new(Mutex::new(None)),
             cache: DistState::with_board_size(board_size),
+            regions: RegionTable::new(),
+            first: to_move,
+        }
+    }
+
+    /// Start from a position written by `Board::to_fen`.
+    pub fn from_fen(fen: &str) -> Result<Amazons, String> {
+        Board::from_fen(fen).map(|(board, to_move)| Amazons::from_board(board, to_move))
+    }
+
+    /// The position the game started from, as a `Board::to_fen` string.
+    pub fn start_fen(&self) -> String {
+        self.tree.root().to_fen(self.first)
+    }
+
+    /// The current position as a `Board::to_fen` string.
+    pub fn to_fen(&self) -> String {
+        self.curr_board().to_fen(self.to_move())
+    }
+
+    /// Take back up to `n` moves, returning how many were taken back.
+    ///
+    /// They stay in the game tree, and `redo` plays them again until
+    /// another move is made.
+    pub fn undo(&mut self, n: usize) -> usize {
+        let mut undone = 0;
+        while undone < n && self.tree.ply() > 0 {
+            self.redo.push(self.tree.current().clone());
+            self.tree.back(1);
+            undone += 1;
+        }
+        self.sync_clock();
+        undone
+    }
+
+    /// Replay up to `n` moves taken back by `undo`, returning how many
+    /// were replayed.
+    pub fn redo(&mut self, n: usize) -> usize {
+        let mut redone = 0;
+        while redone < n {
+            match self.redo.pop() {
+                Some(board) => self.tree.play(board),
+                None => break,
+            }
+            redone += 1;
+        }
+        self.sync_clock();
+        redone
+    }
+
+    /// Play under a time control, starting the clock of the team to move.
+    pub fn set_clock(&mut self, control: TimeControl) {
+        let mut clock = Clock::new(control);
+        clock.start(self.to_move());
+        self.clock = Some(clock);
+    }
+
+    pub fn clock(&self) -> Option<&Clock> {
+        self.clock.as_ref()
+    }
+
+    /// Play book moves from `book` instead of the one shipped for the
+    /// board size, or never with None.
+    pub fn set_book(&mut self, book: Option<OpeningBook>) {
+        self.book = book.map(Arc::new);
+    }
+
+    pub fn book(&self) -> Option<&OpeningBook> {
+        self.book.as_deref()
+    }
+
+    /// Play winning moves from `solved` whenever it knows one.
+    pub fn set_solved(&mut self, solved: Option<SolvedPositions>) {
+        self.solved = solved.map(Arc::new);
+    }
+
+    pub fn solved(&self) -> Option<&SolvedPositions> {
+        self.solved.as_deref()
+    }
+
+    /// Run the clock of the team to move, as after taking moves back.
+    /// The time used so far is charged to the team whose clock ran.
+    fn sync_clock(&mut self) {
+        let team = self.to_move();
+        if let Some(clock) = &mut self.clock {
+            if clock.running() != Some(team) && clock.flagged().is_none() {
+                clock.switch(team);
+            }
+        }
+    }
+
+    /// A move was made: stop its team's time and start the other's.
+    fn press_clock(&mut self) {
+        if let Some(clock) = &mut self.clock {
+            clock.press();
+        }
+    }
+
+    /// The team that has won, because the other is stuck or out of time.
+    pub fn winner(&self) -> Option<Team> {
+        if let Some(team) = self.clock().and_then(|c| c.flagged()) {
+            return Some(team.other());
         }
+        let team = self.to_move();
+        Some(team.other()).filter(|_| self.is_stuck(team))
     }
 
-    /// Revert the last two moves.
-    pub fn undo_2_move(&mut self) {
-        if self.boards.len() >= 3 {
-            self.boards.pop();
-            self.boards.pop();
+    /// The history of the game, with its variations.
+    pub fn tree(&self) -> &GameTree {
+        &self.tree
+    }
+
+    /// Navigate the history with `f`. The next move is played from
+    /// wherever the tree's cursor is left, and if the cursor moved,
+    /// there is nothing left to redo and the clock of the team now to
+    /// move runs.
+    pub fn navigate<R>(&mut self, f: impl FnOnce(&mut GameTree) -> R) -> R {
+        let cursor = self.tree.cursor();
+        let result = f(&mut self.tree);
+        if self.tree.cursor() != cursor {
+            self.redo.clear();
+            self.sync_clock();
         }
+        result
     }
 
     /// All the pieces owned by a team.
     pub fn team_pieces<'s>(&'s self, team: Team) -> impl Iterator<Item=Pos> + 's {
-        self.boards[self.boards.len() - 1].players()
+        self.curr_board().players()
             .filter(move |p| p.team == team)
             .map(|p| p.pos)
     }
 
+    /// Side length of the board, including the border walls.
+    pub fn board_size(&self) -> i8 {
+        self.board_size
+    }
+
+    /// The moves leading to the current board, oldest first.
+    pub fn moves(&self) -> Vec<Move> {
+        self.tree.line()
+    }
+
+    /// The move that led to the current board.
+    pub fn last_move(&self) -> Option<Move> {
+        self.tree.last_move()
+    }
+
+    /// Number of moves leading to the current board.
+    pub fn ply(&self) -> usize {
+        self.tree.ply()
+    }
+
+    /// Team whose turn it is.
+    pub fn to_move(&self) -> Team {
+        self.team_at(self.ply())
+    }
+
+    /// Team to move after `ply` moves from the start.
+    pub fn team_at(&self, ply: usize) -> Team {
+        match ply % 2 {
+            0 => self.first,
+            _ => self.first.other(),
+        }
+    }
+
+    /// Whether `team` has no legal move left, which
+    /// loses the game for them.
+    pub fn is_stuck(&self, team: Team) -> bool {
+        self.curr_board().successors(team).next().is_none()
+    }
+
     /// Try to record a player's move
     ///
     /// Return Err(msg) explaining the error if the move is invalid.
     pub fn player_move(&mut self, team: Team, pos: Pos, mv: Pos, shot: Pos) -> Result<(), String> {
-        let board = self.boards[self.boards.len() - 1].clone();
+        let board = self.curr_board().clone();
 
         for &coord in &[pos, mv, shot] {
             if coord.row >= self.board_size || coord.col >= self.board_size {
-                return Err(format!("Coord {:?} is outside board_size ({}, {})", coord,
+                return Err(format!("Coord {} is outside board_size ({}, {})", coord,
                          self.board_size, self.board_size));
             }
         }
@@ -79,14 +522,16 @@ impl Amazons {
             return Err(format!("Shoot is not in a line!"));
         }
         if let Some(er) = pos.along_line(mv).iter().find(|&&p| board.wall_at(p)) {
-            return Err(format!("Can't move through piece at {:?}", er));
+            return Err(format!("Can't move through piece at {}", er));
         }
         if let Some(er) = mv.along_line(shot).iter().filter(|&&p| p != pos).find(|&&p| board.wall_at(p)) {
-            return Err(format!("Can't place token through piece at {:?}", er));
+            return Err(format!("Can't place token through piece at {}", er));
         }
         if let Some((pi, p)) = board.players().enumerate().find(|(_,play)| play.pos == pos) {
             if p.team == team {
-                self.boards.push(board.with_move(pi, mv, shot));
+                self.tree.play(board.with_move(pi, mv, shot));
+                self.redo.clear();
+                self.press_clock();
                 return Ok(());
             }
         }
@@ -95,25 +540,192 @@ impl Amazons {
 
     /// Compute and make a move for an AI team.
     ///
-    /// Return false if the AI gives up.
-    pub fn ai_move(&mut self, team: Team, strategy: EvalStrategy) -> bool {
+    /// Plays a book move if the opening book has one, picked at random
+    /// by weight, and a winning move if the solved positions, a quick
+    /// `Solver` run or the regions' game values find one. Otherwise
+    /// searches three moves deep, or as deep as the team's share of its
+    /// remaining time allows when there is a clock.
+    ///
+    /// Return what the search did, or None if the AI gives up.
+    pub fn ai_move(&mut self, team: Team, strategy: EvalStrategy) -> Option<SearchInfo> {
+        let handle = self.start_search(team, strategy);
+        self.finish_search(handle)
+    }
+
+    /// Start looking for a move like `ai_move` does, in the background.
+    /// Finish with `finish_search` before changing the position.
+    pub fn start_search(&self, team: Team, strategy: EvalStrategy) -> SearchHandle {
         // TODO Multi-threading based on # of caches
-        let c0 = &mut self.cache;
-        let board = &self.boards[self.boards.len() - 1];
-        return match max_move(&board, team, strategy, 3, c0) {
-            (Some(b), _) => {
-                self.boards.push(b);
-                true
+        let board = self.curr_board().clone();
+        let from = (self.ply(), board.clone());
+        let budget = self.clock().map(|c| c.budget(team));
+        let max_depth = if budget.is_some() { MAX_TIMED_DEPTH } else { 3 };
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+        let progress = Some(receiver);
+        if let Some(next) = self.book.as_ref().and_then(|book| book.choose(&board, team)) {
+            let info = SearchInfo { pv: board.diff_move(&next).into_iter().collect(), book: true, ..SearchInfo::default() };
+            let thread = thread::spawn(move || (Some(next), info));
+            return SearchHandle { stop, progress, thread, from };
+        }
+        if let Some(next) = self.solved.as_ref().and_then(|solved| solved.winning_move(&board, team)) {
+            let info = SearchInfo { pv: board.diff_move(&next).into_iter().collect(), solved: true, ..SearchInfo::default() };
+            let thread = thread::spawn(move || (Some(next), info));
+            return SearchHandle { stop, progress, thread, from };
+        }
+        // A won position whose winning move the database lacks was
+        // settled by counting moves, and is quick to solve again.
+        let solve = quick_to_solve(&board) || self.solved().is_some_and(|s| s.wins(&board, team) == Some(true));
+        let mut search = Search {
+            info: SearchInfo::default(),
+            stop: Some(stop.clone()),
+            deadline: None,
+            progress: Some(sender),
+            table: Some(self.table.clone()),
+            regions: RegionTable::new(),
+            moves: Vec::new(),
+        };
+        let stop_values = stop.clone();
+        let solver = self.solver.clone();
+
+        let thread = thread::spawn(move || {
+            let start = Instant::now();
+            // Solving exactly may take half the budget, and gives up
+            // for the search if it takes more.
+            let deadline = budget.map(|b| start + b / 2);
+            let winning = match solve {
+                true => {
+                    let mut solver = solver.lock().unwrap();
+                    let solver = solver.get_or_insert_with(Solver::new);
+                    solver.limit(Some(stop_values), deadline);
+                    solver.winning_move(&board, team).ok().flatten()
+                },
+                // Late in the game every region may be small enough to
+                // add up the regions' values.
+                false => RegionValues::with_limits(stop_values, deadline).winning_move(&board, team),
+            };
+            if let Some(next) = winning {
+                search.info.pv = board.diff_move(&next).into_iter().collect();
+                search.info.solved = true;
+                search.info.elapsed = start.elapsed();
+                return (Some(next), search.info);
             }
-            (None, _) => {
-                false
+            let (line, _) = deepening(&board, team, strategy, max_depth, budget, &mut search);
+            search.info.elapsed = start.elapsed();
+            (line.into_iter().next(), search.info)
+        });
+        SearchHandle { stop, progress, thread, from }
+    }
+
+    /// Think about `team`'s next move while the other team is to move,
+    /// until stopped with `SearchHandle::cancel`. The `predicted` reply
+    /// is searched first and deepest, then the other likely replies.
+    ///
+    /// The results go to the transposition table, so that the search
+    /// after the reply is played finishes early if it was pondered.
+    pub fn start_ponder(&self, team: Team, strategy: EvalStrategy, predicted: Option<Move>) -> SearchHandle {
+        let board = self.curr_board().clone();
+        let from = (self.ply(), board.clone());
+        let max_depth = if self.clock().is_some() { MAX_TIMED_DEPTH } else { 3 };
+        let stop = Arc::new(AtomicBool::new(false));
+        let progress = None;
+        let mut search = Search {
+            info: SearchInfo::default(),
+            stop: Some(stop.clone()),
+            deadline: None,
+            progress: None,
+            table: Some(self.table.clone()),
+            regions: RegionTable::new(),
+            moves: Vec::new(),
+        };
+
+        let thread = thread::spawn(move || {
+            let start = Instant::now();
+            let mut replies: Vec<Board> = ranked_moves(&board, team.other(), strategy, 1, &mut search)
+                .into_iter().map(|(_, b)| b).collect();
+            if let Some(i) = predicted.and_then(|mv| replies.iter().position(|b| board.diff_move(b).as_ref() == Some(&mv))) {
+                let reply = replies.remove(i);
+                deepening(&reply, team, strategy, max_depth, None, &mut search);
+            }
+            'deepen: for depth in 1..=max_depth {
+                for reply in &replies {
+                    if search.stopped() {
+                        break 'deepen;
+                    }
+                    search.info.depth = depth;
+                    max_move(reply, team, strategy, depth, 0, &mut search);
+                }
             }
+            search.info.elapsed = start.elapsed();
+            (None, search.info)
+        });
+        SearchHandle { stop, progress, thread, from }
+    }
+
+    /// Wait for a search from `start_search` and play its move.
+    ///
+    /// Return what the search did, or None if the AI gives up or the
+    /// position changed since the search started.
+    pub fn finish_search(&mut self, handle: SearchHandle) -> Option<SearchInfo> {
+        self.play_search(handle.join())
+    }
+
+    /// Play the move of a search that has finished, like `finish_search`.
+    pub fn play_search(&mut self, result: SearchResult) -> Option<SearchInfo> {
+        let (ply, board) = &result.from;
+        if *ply != self.ply() || board != self.curr_board() {
+            return None;
         }
+        self.tree.play(result.best?);
+        self.redo.clear();
+        self.press_clock();
+        Some(result.info)
+    }
+
+    /// The best moves for the team to move, best first, searched as deep
+    /// as `ai_move` searches and sharing its transposition table. Only
+    /// the `max_move` candidates are ranked, so at most 14 are returned.
+    pub fn hints(&self, n: usize, strategy: EvalStrategy) -> Vec<Hint> {
+        self.start_hints(n, strategy).join()
+    }
+
+    /// Start looking for hints like `hints` does, in the background.
+    /// With a clock, the search takes no more than the share of time
+    /// the team to move would spend on a move.
+    pub fn start_hints(&self, n: usize, strategy: EvalStrategy) -> HintHandle {
+        let team = self.to_move();
+        let board = self.curr_board().clone();
+        let budget = self.clock().map(|c| c.budget(team));
+        let max_depth = if budget.is_some() { MAX_TIMED_DEPTH } else { 3 };
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut search = Search {
+            stop: Some(stop.clone()),
+            table: Some(self.table.clone()),
+            ..Search::default()
+        };
+        let mut cache = DistState::with_board_size(self.board_size);
+
+        let thread = thread::spawn(move || {
+            ranked_deepening(&board, team, strategy, max_depth, budget, &mut search).into_iter()
+                .take(n)
+                .flat_map(|(score, b)| board.diff_move(&b).map(|mv| Hint {
+                    mv,
+                    score,
+                    territory: b.territory(strategy, &mut cache),
+                }))
+                .collect()
+        });
+        HintHandle { stop, thread }
     }
 
     /// Evaluate the `ix`th last board with an AI heuristic.
     pub fn evaluate(&mut self, ix: usize, team: Team, strategy: EvalStrategy) -> i64 {
-        return self.nth_last_board(ix).evaluate(team, strategy, &mut self.cache);
+        return self.nth_last_board(ix).evaluate(team, strategy, &mut self.regions);
+    }
+
+    /// Square ownership of the current board under a heuristic.
+    pub fn territory(&mut self, strategy: EvalStrategy) -> Territory {
+        self.tree.current().territory(strategy, &mut self.cache)
     }
 
     /// Look back in history for a board state.
@@ -121,65 +733,349 @@ impl Amazons {
     /// If the index is too far back in time, this
     /// returns the first board in history.
     pub fn nth_last_board(&self, i: usize) -> Board {
-        if self.boards.len() > i {
-            return self.boards[self.boards.len() - 1 - i].clone();
-        } else {
-            return self.boards[0].clone();
-        }
+        return self.tree.nth_back(i).clone();
     }
 
     /// The most recent board state.
     pub fn curr_board(&self) -> &Board {
-        return &self.boards[self.boards.len() - 1];
+        return self.tree.current();
+    }
+}
+
+/// The best line for `team` found `depth` moves deep, first move first,
+/// and its score. `board` is `ply` moves from the root of the search.
+/// The line is empty if `team` has no moves, and may be cut short if
+/// the search is stopped.
+fn max_move(board: &Board, team: Team, strategy: EvalStrategy, depth: i32, ply: usize,
+            search: &mut Search) -> (Vec<Board>, i64) {
+    match strategy {
+        EvalStrategy::QueenDistance => max_move_with::<QueenMoves>(board, team, depth, ply, search),
+        EvalStrategy::KingDistance => max_move_with::<KingMoves>(board, team, depth, ply, search),
+    }
+}
+
+/// `max_move` with the strategy fixed at compile time.
+fn max_move_with<R: Reach>(board: &Board, team: Team, depth: i32, ply: usize, search: &mut Search) -> (Vec<Board>, i64) {
+    let canonical = match &search.table {
+        Some(table) => {
+            let canonical = board.canonical_key();
+            if let Some(found) = table.lock().unwrap().get(board, canonical, team, R::STRATEGY, depth) {
+                search.info.table_hits += 1;
+                return found;
+            }
+            Some(canonical)
+        },
+        None => None,
+    };
+    let (line, score) = expand::<R>(board, team, depth, ply, search);
+    if let Some((table, canonical)) = search.table.as_ref().zip(canonical).filter(|_| !search.stopped()) {
+        table.lock().unwrap().insert(board, canonical, team, R::STRATEGY, depth, (&line, score));
     }
+    (line, score)
 }
 
-fn max_move(board: &Board, team: Team, strategy: EvalStrategy, depth: i32, cache: &mut DistState) -> (Option<Board>, i64) {
+/// `max_move_with` without the transposition table.
+fn expand<R: Reach>(board: &Board, team: Team, depth: i32, ply: usize, search: &mut Search) -> (Vec<Board>, i64) {
+    let mut scored = search.take_moves(ply);
+    score_successors::<R>(board, team, ply == 0, &mut scored, search);
+    search.info.visit(ply, scored.len() as u64);
     if depth <= 1 {
-        let best = board.successors(team)
-            .map(|b| (b.evaluate(team, strategy, cache), b))
-            .max_by_key(|it| it.0);
-        if let Some((score, board)) = best {
-            return (Some(board), score);
-        } else {
-            return (None, i64::min_value() + 1);
-        }
+        let best = scored.iter().max_by_key(|it| it.0).map(|(score, b)| (vec![b.clone()], *score));
+        search.give_back_moves(ply, scored);
+        return best.unwrap_or((Vec::new(), i64::MIN + 1));
     }
 
-    let mut best: Option<Board> = None;
-    let mut score: i64 = i64::min_value() + 1;
+    let mut best: Vec<Board> = Vec::new();
+    let mut score: i64 = i64::MIN + 1;
 
-    for (_, b) in top_n(board.successors(team).map(|i| (i.evaluate(team, strategy, cache), i))) {
-        //if score != i64::min_value() && b.evaluate(team, dist_state) < starting_val - 1 {
-        //    // can't do this in the end-game!
-        //    //continue;
-        //}
+    keep_top_n(&mut scored);
+    for (_, b) in &scored {
+        if search.stopped() {
+            break;
+        }
 
-        let (_, resp_score) = max_move(&b, team.other(), strategy, depth-1, cache);
+        let (line, resp_score) = max_move_with::<R>(b, team.other(), depth-1, ply + 1, search);
 
         if score < -resp_score {
             score = -resp_score;
-            best = Some(b);
+            best = std::iter::once(b.clone()).chain(line).collect();
         }
     }
+    search.give_back_moves(ply, scored);
 
-    match best {
-        None => max_move(board, team, strategy, 1, cache),
+    match best.first() {
+        None => max_move_with::<R>(board, team, 1, ply, search),
         _ => (best, score)
 
     }
 }
 
-fn top_n(iter: impl Iterator<Item = (i64, Board)>) -> SmallVec<[(i64, Board); 15]> {
-    let mut vec = SmallVec::<[(i64, Board); 15]>::new();
+/// The candidate moves of `max_move` with their scores at `depth`,
+/// best first.
+fn ranked_moves(board: &Board, team: Team, strategy: EvalStrategy, depth: i32, search: &mut Search) -> Vec<(i64, Board)> {
+    search.info.depth = depth;
+    let mut candidates = Vec::new();
+    match strategy {
+        EvalStrategy::QueenDistance => score_successors::<QueenMoves>(board, team, true, &mut candidates, search),
+        EvalStrategy::KingDistance => score_successors::<KingMoves>(board, team, true, &mut candidates, search),
+    }
+    search.info.visit(0, candidates.len() as u64);
+    keep_top_n(&mut candidates);
+    if depth <= 1 {
+        return candidates;
+    }
+    let mut ranked: Vec<(i64, Board)> = candidates.into_iter()
+        .map(|(_, b)| (-max_move(&b, team.other(), strategy, depth - 1, 1, search).1, b))
+        .collect();
+    ranked.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+    ranked
+}
+
+/// Add each move for `team` from `board` to `scored`, with its score,
+/// counting the positions generated and evaluated in `search.info`.
+///
+/// With `distinct`, only one of the moves that lead to rotations or
+/// reflections of the same position is kept when `board` is symmetric
+/// itself, as the starting positions are.
+fn score_successors<R: Reach>(board: &Board, team: Team, distinct: bool, scored: &mut Vec<(i64, Board)>,
+                              search: &mut Search) {
+    let distinct = distinct && board.symmetries().len() > 1;
+    let mut seen = HashSet::new();
+    for b in board.successors(team) {
+        search.info.nodes += 1;
+        if distinct && !seen.insert(b.canonical_key().0) {
+            continue;
+        }
+        search.info.evals += 1;
+        scored.push((b.evaluate_with::<R>(team, &mut search.regions), b));
+    }
+}
+
+/// Search one depth after another up to `max_depth`, reporting the
+/// best line after each to `search.progress`, while the next depth
+/// looks like it fits in `budget` and the search is not stopped. A
+/// depth still running when the budget is spent is abandoned.
+///
+/// Returns the line of the deepest search that completed. The first
+/// depth always completes, so there is a move whenever one exists.
+fn deepening(board: &Board, team: Team, strategy: EvalStrategy, max_depth: i32, budget: Option<Duration>,
+             search: &mut Search) -> (Vec<Board>, i64) {
+    let start = Instant::now();
+    search.deadline = budget.map(|b| start + b);
+    let mut best = (Vec::new(), i64::MIN + 1);
+    let mut completed = 0;
+    for depth in 1..=max_depth {
+        // Each depth searches the top moves of the one before again.
+        let spent = start.elapsed();
+        if depth > 1 && (search.stopped() || budget.is_some_and(|b| spent * TOP_N as u32 > b.saturating_sub(spent))) {
+            break;
+        }
+        search.info.depth = depth;
+        let result = max_move(board, team, strategy, depth, 0, search);
+        if depth > 1 && search.stopped() {
+            break;
+        }
+        best = result;
+        completed = depth;
+        search.info.elapsed = start.elapsed();
+        search.info.score = best.1;
+        search.info.pv = std::iter::once(board).chain(&best.0).zip(&best.0)
+            .flat_map(|(a, b)| a.diff_move(b))
+            .collect();
+        if let Some(progress) = &search.progress {
+            // Nobody listening is fine.
+            let _ = progress.send(search.info.clone());
+        }
+    }
+    search.info.depth = completed;
+    best
+}
+
+/// `ranked_moves` one depth after another up to `max_depth`, within
+/// `budget` and until the search is stopped, as `deepening` does.
+///
+/// Returns the ranking of the deepest depth that completed.
+fn ranked_deepening(board: &Board, team: Team, strategy: EvalStrategy, max_depth: i32, budget: Option<Duration>,
+                    search: &mut Search) -> Vec<(i64, Board)> {
+    let start = Instant::now();
+    search.deadline = budget.map(|b| start + b);
+    let mut ranked = Vec::new();
+    let mut completed = 0;
+    for depth in 1..=max_depth {
+        let spent = start.elapsed();
+        if depth > 1 && (search.stopped() || budget.is_some_and(|b| spent * TOP_N as u32 > b.saturating_sub(spent))) {
+            break;
+        }
+        let result = ranked_moves(board, team, strategy, depth, search);
+        if depth > 1 && search.stopped() {
+            break;
+        }
+        ranked = result;
+        completed = depth;
+    }
+    search.info.depth = completed;
+    ranked
+}
+
+/// Deepest a clocked search goes, however much time is left.
+const MAX_TIMED_DEPTH: i32 = 6;
+
+/// Moves `max_move` looks further into at each depth.
+const TOP_N: usize = 14;
+
+/// Sort the moves best first, keeping the order they came in among
+/// equals, and drop all but the `TOP_N` that `max_move` looks further
+/// into.
+fn keep_top_n(scored: &mut Vec<(i64, Board)>) {
+    scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+    scored.truncate(TOP_N);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Play the first move found for the team to move.
+    fn play_any(amazons: &mut Amazons) {
+        let team = amazons.to_move();
+        let board = amazons.curr_board().clone();
+        let mv = board.successors(team).next().and_then(|b| board.diff_move(&b)).unwrap();
+        amazons.player_move(team, mv.player.pos, mv.new_pos, mv.new_shot).unwrap();
+    }
+
+    #[test]
+    fn search_past_its_deadline_stops_within_a_depth() {
+        let board = Amazons::new_10x10().curr_board().clone();
+        let mut search = Search { deadline: Some(Instant::now()), ..Search::default() };
+        let (line, _) = max_move(&board, Team::Red, EvalStrategy::QueenDistance, 4, 0, &mut search);
+        assert_eq!(line.len(), 1);
+        assert_eq!(search.info.branching().len(), 1);
+    }
+
+    #[test]
+    fn stopped_search_counts_its_fallback_at_the_root() {
+        let board = Amazons::new_8x8().curr_board().clone();
+        let stop = Arc::new(AtomicBool::new(true));
+        let mut search = Search { stop: Some(stop), ..Search::default() };
+        search.info.depth = 3;
+        max_move(&board, Team::Red, EvalStrategy::QueenDistance, 3, 0, &mut search);
+        assert_eq!(search.info.branching().len(), 1);
+    }
+
+    #[test]
+    fn evaluations_skip_symmetric_duplicates() {
+        let board = Amazons::new_10x10().curr_board().clone();
+        let moves = board.successors(Team::Red).count() as u64;
+        let mut search = Search::default();
+        ranked_moves(&board, Team::Red, EvalStrategy::QueenDistance, 1, &mut search);
+        assert_eq!(search.info.nodes, moves);
+        assert!(search.info.evals < moves);
+
+        let mut amazons = Amazons::new_10x10();
+        play_any(&mut amazons);
+        let board = amazons.curr_board().clone();
+        let moves = board.successors(Team::Blue).count() as u64;
+        let mut search = Search::default();
+        max_move(&board, Team::Blue, EvalStrategy::QueenDistance, 2, 0, &mut search);
+        assert_eq!(search.info.branching()[0], moves as f64);
+        assert_eq!(search.info.evals, search.info.nodes);
+        assert!(search.info.nodes > moves);
+    }
+
+    #[test]
+    fn clock_charges_time_used_before_an_undo() {
+        let mut amazons = Amazons::new_5x5();
+        let control = TimeControl::SuddenDeath(Duration::from_secs(60));
+        amazons.set_clock(control);
+        play_any(&mut amazons);
+        std::thread::sleep(Duration::from_millis(30));
+        amazons.undo(1);
+        let clock = amazons.clock().unwrap();
+        assert_eq!(clock.running(), Some(Team::Red));
+        assert!(clock.remaining(Team::Blue) <= Duration::from_secs(60) - Duration::from_millis(30));
+    }
+
+    #[test]
+    fn clock_follows_the_cursor_back() {
+        let mut amazons = Amazons::new_5x5();
+        amazons.set_clock(TimeControl::SuddenDeath(Duration::from_secs(60)));
+        play_any(&mut amazons);
+        play_any(&mut amazons);
+        amazons.navigate(|t| t.back(1));
+        assert_eq!(amazons.clock().unwrap().running(), Some(amazons.to_move()));
+    }
+
+    #[test]
+    fn stopped_hints_keep_the_first_depth() {
+        let amazons = Amazons::new_10x10();
+        let handle = amazons.start_hints(3, EvalStrategy::QueenDistance);
+        let start = Instant::now();
+        handle.stop();
+        let hints = handle.join();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(hints.len(), 3);
+        assert!(hints.windows(2).all(|w| w[0].score >= w[1].score));
+    }
+
+    #[test]
+    fn pondering_stops_when_cancelled() {
+        let amazons = Amazons::new_10x10();
+        let handle = amazons.start_ponder(Team::Blue, EvalStrategy::QueenDistance, None);
+        thread::sleep(Duration::from_millis(50));
+        assert!(handle.progress().is_empty());
+        let start = Instant::now();
+        let info = handle.cancel();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(info.depth < 3);
+    }
 
-    iter.for_each(|new| {
-        match vec.binary_search_by_key(& -new.0, |a| -a.0) {
-            Ok(i) => vec.insert(i, new),
-            Err(i) => vec.insert(i, new),
+    #[test]
+    fn move_now_cuts_an_exact_solve_short() {
+        let mut amazons = Amazons::new_5x5();
+        while !quick_to_solve(amazons.curr_board()) {
+            play_any(&mut amazons);
         }
-        vec.truncate(14)
-    });
+        let team = amazons.to_move();
+        let handle = amazons.start_search(team, EvalStrategy::QueenDistance);
+        let start = Instant::now();
+        handle.stop();
+        let result = handle.join();
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(amazons.play_search(result).is_some());
+    }
+
+    #[test]
+    fn search_result_is_played() {
+        let mut amazons = Amazons::new_5x5();
+        let handle = amazons.start_search(Team::Red, EvalStrategy::QueenDistance);
+        assert!(amazons.finish_search(handle).is_some());
+        assert_eq!(amazons.ply(), 1);
+    }
 
-    return vec;
+    #[test]
+    fn stale_search_result_is_dropped() {
+        let mut amazons = Amazons::new_5x5();
+        let handle = amazons.start_search(Team::Red, EvalStrategy::QueenDistance);
+        play_any(&mut amazons);
+        let after = amazons.curr_board().clone();
+        assert!(amazons.finish_search(handle).is_none());
+        assert_eq!(amazons.ply(), 1);
+        assert_eq!(*amazons.curr_board(), after);
+    }
+
+    #[test]
+    fn search_from_another_line_is_dropped() {
+        let mut amazons = Amazons::new_5x5();
+        let start = amazons.curr_board().clone();
+        let first = start.successors(Team::Red).next().and_then(|b| start.diff_move(&b)).unwrap();
+        let other = start.successors(Team::Red).nth(1).and_then(|b| start.diff_move(&b)).unwrap();
+        amazons.player_move(Team::Red, first.player.pos, first.new_pos, first.new_shot).unwrap();
+        let handle = amazons.start_search(Team::Blue, EvalStrategy::QueenDistance);
+        amazons.undo(1);
+        amazons.player_move(Team::Red, other.player.pos, other.new_pos, other.new_shot).unwrap();
+        let after = amazons.curr_board().clone();
+        assert!(amazons.finish_search(handle).is_none());
+        assert_eq!(amazons.ply(), 1);
+        assert_eq!(*amazons.curr_board(), after);
+    }
 }
@@ -0,0 +1,209 @@
+use super::board::*;
+
+use std::fmt;
+
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", (b'a' + self.col as u8 - 1) as char, self.row)
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}/{}", self.player.pos, self.new_pos, self.new_shot)
+    }
+}
+
+/// Read a square like `c3`.
+///
+/// The file letter counts columns from `a`, and the rank is the row
+/// number, so rank 1 is the row drawn at the bottom.
+pub fn parse_pos(s: &str) -> Option<Pos> {
+    let mut chars = s.chars();
+    let file = chars.next().filter(|c| c.is_ascii_alphabetic())?.to_ascii_lowercase();
+    let rank: i8 = chars.as_str().parse().ok()?;
+    if rank < 1 || !chars.as_str().starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(Pos { row: rank, col: (file as u8 - b'a' + 1) as i8 })
+}
+
+/// Read a move as origin, destination and arrow.
+///
+/// Accepts `d1-d7/g7`, which is what `Move` displays as, and also
+/// `d1-d7(g7)` and `d1 d7 g7`.
+pub fn parse_move(s: &str) -> Option<(Pos, Pos, Pos)> {
+    let s = s.trim();
+    let parts: Vec<&str> = if s.contains(char::is_whitespace) {
+        s.split_ascii_whitespace().collect()
+    } else {
+        s.trim_end_matches(')').split(&['-', '/', '('][..]).collect()
+    };
+    match parts.as_slice() {
+        [a, b, c] => Some((parse_pos(a)?, parse_pos(b)?, parse_pos(c)?)),
+        _ => None,
+    }
+}
+
+impl Board {
+    /// One-line position string, in the spirit of chess FEN.
+    ///
+    /// Ranks are listed from the top, separated by `/`. Within a rank a
+    /// number counts empty squares, `#` is an arrow, and `R` and `B` are
+    /// Red's and Blue's pieces. The side to move, `r` or `b`, follows
+    /// after a space. The start of `Amazons::new_8x8` is
+    /// `8/8/2B2B2/8/8/2R2R2/8/8 r`.
+    pub fn to_fen(&self, to_move: Team) -> String {
+        let n = self.board_size() - 2;
+        let mut ranks = Vec::new();
+        for row in (1..=n).rev() {
+            let mut rank = String::new();
+            let mut empty = 0;
+            for col in 1..=n {
+                let pos = Pos { row, col };
+                let c = match self.players().find(|p| p.pos == pos) {
+                    Some(p) => team_char(p.team),
+                    None if self.wall_at(pos) => '#',
+                    None => {
+                        empty += 1;
+                        continue;
+                    },
+                };
+                if empty > 0 {
+                    rank.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                rank.push(c);
+            }
+            if empty > 0 {
+                rank.push_str(&empty.to_string());
+            }
+            ranks.push(rank);
+        }
+        format!("{} {}", ranks.join("/"), team_char(to_move).to_ascii_lowercase())
+    }
+
+    /// Read a position written by `to_fen`, returning the board and the
+    /// side to move.
+    pub fn from_fen(fen: &str) -> Result<(Board, Team), String> {
+        let fields: Vec<&str> = fen.split_ascii_whitespace().collect();
+        let (placement, side) = match fields.as_slice() {
+            [placement, side] => (*placement, *side),
+            _ => return Err(String::from("expected the placement and the side to move")),
+        };
+        let to_move = match side {
+            "r" => Team::Red,
+            "b" => Team::Blue,
+            _ => return Err(format!("side to move must be r or b, not {:?}", side)),
+        };
+
+        let ranks: Vec<&str> = placement.split('/').collect();
+        let n = ranks.len();
+        let mut players = Vec::new();
+        let mut arrows = Vec::new();
+        for (i, rank) in ranks.iter().enumerate() {
+            let row = (n - i) as i8;
+            let mut col: usize = 0;
+            let mut digits = String::new();
+            for c in rank.chars().chain(std::iter::once('/')) {
+                if c.is_ascii_digit() {
+                    digits.push(c);
+                    continue;
+                }
+                if !digits.is_empty() {
+                    let run = digits.parse::<usize>().map_err(|e| format!("rank {}: {}", row, e))?;
+                    col = col.saturating_add(run);
+                    digits.clear();
+                }
+                if c == '/' {
+                    break;
+                }
+                if col >= n {
+                    return Err(format!("rank {} has more than {} squares", row, n));
+                }
+                let pos = Pos { row, col: col as i8 + 1 };
+                match c {
+                    '#' => arrows.push(pos),
+                    'R' => players.push(Player { team: Team::Red, pos }),
+                    'B' => players.push(Player { team: Team::Blue, pos }),
+                    _ => return Err(format!("rank {}: unknown square {:?}", row, c)),
+                }
+                col += 1;
+            }
+            if col != n {
+                return Err(format!("rank {} has {} squares, expected {}", row, col, n));
+            }
+        }
+
+        let board = Board::from_squares(n, players, &arrows)?;
+        Ok((board, to_move))
+    }
+}
+
+fn team_char(team: Team) -> char {
+    match team {
+        Team::Red => 'R',
+        Team::Blue => 'B',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::Amazons;
+
+    #[test]
+    fn squares_read_back() {
+        assert_eq!(parse_pos("c3"), Some(Pos { row: 3, col: 3 }));
+        assert_eq!(parse_pos("C3"), Some(Pos { row: 3, col: 3 }));
+        assert_eq!(parse_pos("k11"), Some(Pos { row: 11, col: 11 }));
+        for bad in ["", "c", "3c", "c0", "c-1", "c+1", "c3x"].iter() {
+            assert_eq!(parse_pos(bad), None, "{:?}", bad);
+        }
+        let p = Pos { row: 10, col: 7 };
+        assert_eq!(parse_pos(&p.to_string()), Some(p));
+    }
+
+    #[test]
+    fn moves_read_in_every_notation() {
+        let expected = Some((Pos { row: 1, col: 4 }, Pos { row: 7, col: 4 }, Pos { row: 7, col: 7 }));
+        for text in ["d1-d7/g7", "d1-d7(g7)", "d1 d7 g7", " d1-d7/g7 "].iter() {
+            assert_eq!(parse_move(text), expected, "{:?}", text);
+        }
+        assert_eq!(parse_move("d1-d7"), None);
+        assert_eq!(parse_move("d1-d7/g7/h8"), None);
+    }
+
+    #[test]
+    fn fen_round_trips() {
+        for start in [Amazons::new_5x5(), Amazons::new_8x8(), Amazons::new_10x10()].iter() {
+            let mut board = start.curr_board().clone();
+            let mut team = Team::Red;
+            for ply in 0..8 {
+                let fen = board.to_fen(team);
+                // The pieces may come back in another order.
+                let (read, to_move) = Board::from_fen(&fen).unwrap();
+                assert_eq!((read.key(), to_move), (board.key(), team), "{}", fen);
+                assert_eq!(read.to_fen(to_move), fen);
+                let next: Vec<Board> = board.successors(team).collect();
+                board = next[(ply * 53 + 7) % next.len()].clone();
+                team = team.other();
+            }
+        }
+        assert_eq!(Amazons::new_8x8().curr_board().to_fen(Team::Red), "8/8/2B2B2/8/8/2R2R2/8/8 r");
+        let eleven = "R10/11/11/11/11/11/11/11/11/11/10B b";
+        assert_eq!(Board::from_fen(eleven).map(|(b, _)| b.to_fen(Team::Blue)), Ok(String::from(eleven)));
+    }
+
+    #[test]
+    fn bad_fens_are_rejected() {
+        let bad_fens = [
+            "8/8/8 r", "5/5/5/5/5", "5/5/5/5/5 x", "5/5/5/5/4 r", "2X2/5/5/5/R4 r",
+            "R11/12/12/12/12/12/12/12/12/12/12/11B r",
+            "127R/5/5/5/5 r", "300R/5/5/5/5 r", "18446744073709551615R/5/5/5/5 r",
+        ];
+        for bad in bad_fens.iter() {
+            assert!(Board::from_fen(bad).is_err(), "{:?}", bad);
+        }
+    }
+}
@@ -0,0 +1,557 @@
+use super::*;
+use super::notation::*;
+use super::tree::*;
+
+use std::fmt;
+
+/// The tags `GameRecord::from_game` sets from the game itself.
+const GAME_TAGS: [&str; 5] = ["Size", "FEN", "TimeControl", "Result", "Termination"];
+
+/// One game: header tags, then the moves from the starting position.
+///
+/// The text form follows PGN, as used by online Amazons sites:
+///
+/// ```text
+/// [Event "Club night"]
+/// [White "Alice"]
+/// [Black "Bob"]
+/// [Result "1-0"]
+///
+/// 1. d1-d7/g7 g10-g2/c6 (1... j7-c7/c2 {branch: sharp}) 2. a4-b5/b8 ...
+/// 1-0
+/// ```
+///
+/// White is the Red team and Black is Blue. The start is the standard
+/// 10x10 position unless a `Size` tag names another start, or a `FEN`
+/// tag holds a `Board::to_fen` string. A variation in parentheses
+/// replaces the move before it, and a `{branch: name}` comment names
+/// the position after a move. Other comments and `!`/`?` remarks are
+/// kept with the move they follow.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameRecord {
+    pub tags: Vec<(String, String)>,
+    pub line: Line,
+    /// `1-0` if White won, `0-1` if Black won, `*` while in progress.
+    pub result: String,
+}
+
+/// A sequence of moves with the variations branching off it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Line {
+    pub moves: Vec<(Pos, Pos, Pos)>,
+    /// Lines played instead of the move at an index, in order.
+    pub variations: Vec<(usize, Line)>,
+    /// Names of the positions after the move at an index.
+    pub names: Vec<(usize, String)>,
+    /// Remarks on the move at an index, such as `?!`, `??` or `!`.
+    pub glyphs: Vec<(usize, String)>,
+    /// Text comments after the move at an index.
+    pub comments: Vec<(usize, String)>,
+}
+
+impl GameRecord {
+    /// Record the game with all its variations, with only the tags it implies.
+    ///
+    /// The result is that of the main line.
+    pub fn from_game(amazons: &Amazons) -> GameRecord {
+        let mut record = GameRecord {
+            tags: Vec::new(),
+            line: Line::from_tree(amazons.tree()),
+            result: String::from("*"),
+        };
+        let start = amazons.start_fen();
+        match [(5, Amazons::new_5x5()), (8, Amazons::new_8x8()), (10, Amazons::new_10x10())]
+            .iter().find(|(_, a)| a.start_fen() == start) {
+            Some((10, _)) => {},
+            Some((size, _)) => record.set_tag("Size", &size.to_string()),
+            None => record.set_tag("FEN", &start),
+        }
+        if let Some(clock) = amazons.clock() {
+            record.set_tag("TimeControl", &clock.control().to_string());
+        }
+        let (ply, end) = amazons.tree().main_end();
+        let team = amazons.team_at(ply);
+        if let Some(flagged) = amazons.clock().and_then(|c| c.flagged()) {
+            record.result = String::from(result_for(flagged.other()));
+            record.set_tag("Termination", "time forfeit");
+        } else if end.successors(team).next().is_none() {
+            record.result = String::from(result_for(team.other()));
+        }
+        record.set_tag("Result", &record.result.clone());
+        record
+    }
+
+    /// Record `amazons`, a game loaded from this record, like
+    /// `from_game`, keeping this record's tags for what the game does
+    /// not decide, such as the players and the event.
+    pub fn updated(&self, amazons: &Amazons) -> GameRecord {
+        let game = GameRecord::from_game(amazons);
+        let mut record = GameRecord { tags: self.tags.clone(), ..game.clone() };
+        record.tags.retain(|(key, _)| !GAME_TAGS.contains(&key.as_str()) || game.tag(key).is_some());
+        for (key, value) in &game.tags {
+            record.set_tag(key, value);
+        }
+        record
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// Replace a tag's value, or add it at the end.
+    pub fn set_tag(&mut self, key: &str, value: &str) {
+        match self.tags.iter_mut().find(|(k, _)| k == key) {
+            Some(tag) => tag.1 = value.to_string(),
+            None => self.tags.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    /// The position before the first move.
+    pub fn start(&self) -> Result<Amazons, String> {
+        if let Some(fen) = self.tag("FEN") {
+            return Amazons::from_fen(fen);
+        }
+        match self.tag("Size").unwrap_or("10") {
+            "5" | "5x5" => Ok(Amazons::new_5x5()),
+            "8" | "8x8" => Ok(Amazons::new_8x8()),
+            "10" | "10x10" => Ok(Amazons::new_10x10()),
+            size => Err(format!("no standard start for size {:?}, use a FEN tag", size)),
+        }
+    }
+
+    /// Play every move, variations included, checking that each one is
+    /// legal. The game is left at the end of the main line.
+    pub fn replay(&self) -> Result<Amazons, String> {
+        let mut amazons = self.start()?;
+        replay_line(&mut amazons, &self.line)?;
+        Ok(amazons)
+    }
+}
+
+impl Line {
+    /// Every move in a game tree, from its root.
+    pub fn from_tree(tree: &GameTree) -> Line {
+        // Open variations, each with the index in its parent it replaces.
+        let mut stack = vec![(0, Line::default())];
+        tree.walk(&mut |step| match step {
+            TreeStep::Move(_, mv, name, notes) => {
+                let line = &mut stack.last_mut().unwrap().1;
+                line.moves.push((mv.player.pos, mv.new_pos, mv.new_shot));
+                let at = line.moves.len() - 1;
+                if let Some(name) = name {
+                    line.names.push((at, name));
+                }
+                if let Some(glyph) = notes.glyph {
+                    line.glyphs.push((at, glyph));
+                }
+                line.comments.extend(notes.comments.into_iter().map(|c| (at, c)));
+            },
+            TreeStep::Enter => {
+                let at = stack.last().unwrap().1.moves.len() - 1;
+                stack.push((at, Line::default()));
+            },
+            TreeStep::Leave => {
+                let (at, line) = stack.pop().unwrap();
+                stack.last_mut().unwrap().1.variations.push((at, line));
+            },
+        });
+        stack.pop().unwrap().1
+    }
+}
+
+/// Play a line from the current position, then each of its variations
+/// from where they branch, leaving the cursor at the end of the line.
+/// Names, glyphs and comments go on the tree with their moves.
+fn replay_line(amazons: &mut Amazons, line: &Line) -> Result<(), String> {
+    for (i, &(p, m, s)) in line.moves.iter().enumerate() {
+        let ply = amazons.ply() + 1;
+        let team = amazons.to_move();
+        amazons.player_move(team, p, m, s)
+            .map_err(|msg| format!("ply {} ({}-{}/{}): {}", ply, p, m, s, msg))?;
+        for (_, name) in line.names.iter().filter(|(at, _)| *at == i) {
+            amazons.navigate(|tree| tree.set_name(name));
+        }
+        let notes = Notes {
+            glyph: line.glyphs.iter().find(|(at, _)| *at == i).map(|(_, g)| g.clone()),
+            comments: line.comments.iter().filter(|(at, _)| *at == i).map(|(_, c)| c.clone()).collect(),
+        };
+        amazons.navigate(|tree| tree.set_notes(notes));
+
+        for (_, variation) in line.variations.iter().filter(|(at, _)| *at == i) {
+            amazons.navigate(|tree| tree.back(1));
+            replay_line(amazons, variation)?;
+            amazons.navigate(|tree| {
+                tree.jump(ply - 1);
+                tree.enter(0);
+            });
+        }
+    }
+    Ok(())
+}
+
+fn result_for(winner: Team) -> &'static str {
+    match winner {
+        Team::Red => "1-0",
+        Team::Blue => "0-1",
+    }
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (k, v) in &self.tags {
+            writeln!(f, "[{} \"{}\"]", k, v.replace('\\', "\\\\").replace('"', "\\\""))?;
+        }
+        writeln!(f)?;
+
+        // White's first move is missing when Black moves first.
+        let first = match self.start().map(|a| a.to_move()) {
+            Ok(Team::Blue) => 1,
+            _ => 0,
+        };
+        let mut tokens = Vec::new();
+        line_tokens(&self.line, first, &mut tokens);
+        tokens.push(self.result.clone());
+
+        let mut line = String::new();
+        for t in tokens {
+            if !line.is_empty() && line.len() + 1 + t.len() > 79 {
+                writeln!(f, "{}", line)?;
+                line.clear();
+            }
+            if !line.is_empty() && !line.ends_with('(') && t != ")" {
+                line.push(' ');
+            }
+            line.push_str(&t);
+        }
+        writeln!(f, "{}", line)
+    }
+}
+
+/// Move text for a line starting after `ply` moves.
+fn line_tokens(line: &Line, ply: usize, tokens: &mut Vec<String>) {
+    for (i, (p, m, s)) in line.moves.iter().enumerate() {
+        let ply = ply + i;
+        match ply % 2 {
+            0 => tokens.push(format!("{}.", ply / 2 + 1)),
+            // Black's move needs its number after a break in the text.
+            _ if i == 0 || line.variations.iter().any(|(at, _)| at + 1 == i) =>
+                tokens.push(format!("{}...", ply / 2 + 1)),
+            _ => {},
+        }
+        let glyph = line.glyphs.iter().find(|(at, _)| *at == i).map_or("", |(_, g)| g.as_str());
+        tokens.push(format!("{}-{}/{}{}", p, m, s, glyph));
+        for (_, name) in line.names.iter().filter(|(at, _)| *at == i) {
+            tokens.push(format!("{{branch: {}}}", name));
+        }
+        for (_, comment) in line.comments.iter().filter(|(at, _)| *at == i) {
+            tokens.push(format!("{{{}}}", comment));
+        }
+        for (_, variation) in line.variations.iter().filter(|(at, _)| *at == i) {
+            tokens.push(String::from("("));
+            line_tokens(variation, ply, tokens);
+            tokens.push(String::from(")"));
+        }
+    }
+}
+
+/// Read every game in a file of records.
+///
+/// Move numbers and `;` line comments are skipped, as are `{comments}`
+/// before the first move of a line.
+pub fn parse_records(text: &str) -> Result<Vec<GameRecord>, String> {
+    let mut records = Vec::new();
+    let mut current: Option<GameRecord> = None;
+    // Variations being read, each with the index in its parent it replaces.
+    let mut open: Vec<(usize, Line)> = Vec::new();
+    let mut comment: Option<String> = None;
+
+    for (n, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if comment.is_none() && trimmed.starts_with('[') {
+            let tag = parse_tag(trimmed).ok_or(format!("line {}: bad tag {:?}", n + 1, trimmed))?;
+            // Tags after moves start the next game.
+            if current.as_ref().is_some_and(|r| !r.line.moves.is_empty() || r.result != "*") {
+                records.extend(current.take());
+            }
+            current.get_or_insert_with(empty_record).tags.push(tag);
+            continue;
+        }
+
+        for token in tokenize(line, &mut comment) {
+            let record = current.get_or_insert_with(empty_record);
+            let line = match open.last_mut() {
+                Some((_, line)) => line,
+                None => &mut record.line,
+            };
+            match token {
+                Token::Comment(text) => {
+                    if line.moves.is_empty() {
+                        continue;
+                    }
+                    let at = line.moves.len() - 1;
+                    match text.trim().strip_prefix("branch:") {
+                        Some(name) => line.names.push((at, name.trim().to_string())),
+                        None => line.comments.push((at, text.trim().to_string())),
+                    }
+                },
+                Token::Open => {
+                    if line.moves.is_empty() {
+                        return Err(format!("line {}: variation before any move", n + 1));
+                    }
+                    let at = line.moves.len() - 1;
+                    open.push((at, Line::default()));
+                },
+                Token::Close => {
+                    let (at, variation) = open.pop().ok_or(format!("line {}: unmatched ')'", n + 1))?;
+                    match open.last_mut() {
+                        Some((_, line)) => line.variations.push((at, variation)),
+                        None => record.line.variations.push((at, variation)),
+                    }
+                },
+                Token::Word(word) => {
+                    // Move numbers may be glued to the move, as in `1.d1-d7/g7`.
+                    let word = match word.rfind('.') {
+                        Some(dot) if word[..dot].chars().all(|c| c.is_ascii_digit() || c == '.') => &word[dot + 1..],
+                        _ => &word[..],
+                    };
+                    if word.is_empty() {
+                        continue;
+                    }
+                    if ["1-0", "0-1", "*"].contains(&word) {
+                        record.result = word.to_string();
+                    } else {
+                        let body = word.trim_end_matches(&['!', '?'][..]);
+                        let mv = parse_move(body)
+                            .ok_or(format!("line {}: bad move {:?}", n + 1, word))?;
+                        line.moves.push(mv);
+                        if body.len() < word.len() {
+                            line.glyphs.push((line.moves.len() - 1, word[body.len()..].to_string()));
+                        }
+                    }
+                },
+            }
+        }
+    }
+    if !open.is_empty() {
+        return Err(String::from("unclosed variation at end of file"));
+    }
+    records.extend(current);
+    Ok(records)
+}
+
+enum Token {
+    Word(String),
+    Comment(String),
+    Open,
+    Close,
+}
+
+/// Split a line of move text. `comment` holds a `{comment}` that spans
+/// lines until its closing brace.
+///
+/// A parenthesis right after a move's two squares that holds just one
+/// square is the move's shot, as in `d1-d7(g7)`; any other parenthesis
+/// opens or closes a variation.
+fn tokenize(line: &str, comment: &mut Option<String>) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut shot_end = 0;
+    for (i, c) in line.char_indices() {
+        if i < shot_end {
+            continue;
+        }
+        if let Some(text) = comment {
+            if c == '}' {
+                tokens.push(Token::Comment(text.clone()));
+                *comment = None;
+            } else {
+                text.push(c);
+            }
+            continue;
+        }
+        if c == '(' {
+            if let Some(len) = shot_after(&word, &line[i..]) {
+                word.push_str(&line[i..i + len]);
+                shot_end = i + len;
+                continue;
+            }
+        }
+        if (c.is_whitespace() || ['{', '(', ')', ';'].contains(&c)) && !word.is_empty() {
+            tokens.push(Token::Word(word.clone()));
+            word.clear();
+        }
+        match c {
+            '{' => *comment = Some(String::new()),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            ';' => return tokens,
+            c if c.is_whitespace() => {},
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(Token::Word(word));
+    }
+    if let Some(text) = comment {
+        text.push(' ');
+    }
+    tokens
+}
+
+/// The length of the `(square)` that `rest` starts with, if `word` is a
+/// move still waiting for its shot.
+fn shot_after(word: &str, rest: &str) -> Option<usize> {
+    let mut squares = word.split('-');
+    match (squares.next(), squares.next(), squares.next()) {
+        (Some(from), Some(to), None) if parse_pos(from).is_some() && parse_pos(to).is_some() => {},
+        _ => return None,
+    }
+    let close = rest.find(')')?;
+    parse_pos(&rest[1..close])?;
+    Some(close + 1)
+}
+
+fn empty_record() -> GameRecord {
+    GameRecord {
+        tags: Vec::new(),
+        line: Line::default(),
+        result: String::from("*"),
+    }
+}
+
+/// Read `[Key "Value"]`.
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?.trim();
+    let space = inner.find(char::is_whitespace)?;
+    let key = inner[..space].to_string();
+    let quoted = inner[space..].trim().strip_prefix('"')?.strip_suffix('"')?;
+
+    let mut value = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            value.extend(chars.next());
+        } else {
+            value.push(c);
+        }
+    }
+    Some((key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE_10X10: &str = include_str!("../../games/example-10x10.txt");
+    const EXAMPLE_5X5: &str = include_str!("../../games/example-5x5.txt");
+
+    fn square(s: &str) -> Pos {
+        parse_pos(s).unwrap()
+    }
+
+    #[test]
+    fn example_games_round_trip() {
+        for text in [EXAMPLE_10X10, EXAMPLE_5X5].iter() {
+            let record = parse_records(text).unwrap().remove(0);
+            let amazons = record.replay().unwrap();
+            let reread = parse_records(&record.to_string()).unwrap().remove(0);
+            assert_eq!(reread, record);
+            assert_eq!(reread.replay().unwrap().moves(), amazons.moves());
+        }
+    }
+
+    #[test]
+    fn shot_in_parentheses_is_part_of_the_move() {
+        let record = parse_records(EXAMPLE_10X10).unwrap().remove(0);
+        assert_eq!(record.line.moves[1], (square("g10"), square("g7"), square("c3")));
+        assert!(record.line.variations.is_empty());
+    }
+
+    #[test]
+    fn variation_right_after_a_move_is_not_a_shot() {
+        let text = "[Size \"5\"]\n\n1. b2-b3/c3(1. b2-c3/c4) d4-d3/e2 *\n";
+        let record = parse_records(text).unwrap().remove(0);
+        assert_eq!(record.line.moves.len(), 2);
+        assert_eq!(record.line.variations.len(), 1);
+        let (index, variation) = &record.line.variations[0];
+        assert_eq!(*index, 0);
+        assert_eq!(variation.moves, vec![(square("b2"), square("c3"), square("c4"))]);
+
+        let glued = "[Size \"5\"]\n\n1. b2-b3(c3)(1. b2-c3(c4)) d4-d3/e2 *\n";
+        assert_eq!(parse_records(glued).unwrap().remove(0), record);
+    }
+
+    #[test]
+    fn updated_record_keeps_loaded_tags() {
+        let loaded = parse_records(EXAMPLE_10X10).unwrap().remove(0);
+        let mut amazons = loaded.replay().unwrap();
+        let team = amazons.to_move();
+        let board = amazons.curr_board().clone();
+        let mv = board.successors(team).next().and_then(|b| board.diff_move(&b)).unwrap();
+        amazons.player_move(team, mv.player.pos, mv.new_pos, mv.new_shot).unwrap();
+
+        let saved = loaded.updated(&amazons);
+        assert_eq!(saved.tag("Event"), Some("Casual game"));
+        assert_eq!(saved.tag("White"), Some("Alice"));
+        assert_eq!(saved.tag("Black"), Some("Bob"));
+        assert_eq!(saved.tag("Result"), Some("*"));
+        assert_eq!(saved.tags[0].0, "Event");
+
+        let reread = parse_records(&saved.to_string()).unwrap().remove(0);
+        assert_eq!(reread.tags, saved.tags);
+        assert_eq!(reread.replay().unwrap().moves(), amazons.moves());
+    }
+
+    #[test]
+    fn saving_keeps_comments_and_glyphs() {
+        let text = "[Size \"5\"]\n\n1. b2-b3/c3?! {too slow} (1. b2-c3/c4! {better} {branch: sharp})\n\
+                    d4-d3/e2 {the only move} *\n";
+        let loaded = parse_records(text).unwrap().remove(0);
+        assert_eq!(loaded.line.glyphs, vec![(0, String::from("?!"))]);
+        assert_eq!(loaded.line.comments.len(), 2);
+        let mut amazons = loaded.replay().unwrap();
+        let team = amazons.to_move();
+        let board = amazons.curr_board().clone();
+        let mv = board.successors(team).next().and_then(|b| board.diff_move(&b)).unwrap();
+        amazons.player_move(team, mv.player.pos, mv.new_pos, mv.new_shot).unwrap();
+
+        let saved = loaded.updated(&amazons);
+        let reread = parse_records(&saved.to_string()).unwrap().remove(0);
+        for record in [&saved, &reread].iter() {
+            assert_eq!(record.line.moves.len(), 3);
+            assert_eq!(record.line.glyphs, loaded.line.glyphs);
+            assert_eq!(record.line.comments, loaded.line.comments);
+            assert_eq!(record.line.variations, loaded.line.variations);
+        }
+    }
+
+    #[test]
+    fn black_to_move_at_the_start_numbers_from_its_move() {
+        let mut amazons = Amazons::from_fen("5/1B1B1/5/1R1R1/5 b").unwrap();
+        for _ in 0..3 {
+            let team = amazons.to_move();
+            let board = amazons.curr_board().clone();
+            let mv = board.successors(team).next().and_then(|b| board.diff_move(&b)).unwrap();
+            amazons.player_move(team, mv.player.pos, mv.new_pos, mv.new_shot).unwrap();
+        }
+        let record = GameRecord::from_game(&amazons);
+        let text = record.to_string();
+        let moves: Vec<&str> = text.lines().last().unwrap().split(' ').collect();
+        assert_eq!((moves[0], moves[2], moves[5]), ("1...", "2.", "*"));
+
+        let reread = parse_records(&text).unwrap().remove(0);
+        assert_eq!(reread, record);
+        assert_eq!(reread.replay().unwrap().moves(), amazons.moves());
+    }
+
+    #[test]
+    fn updated_record_drops_what_the_game_no_longer_says() {
+        let mut loaded = GameRecord::from_game(&Amazons::new_5x5());
+        loaded.set_tag("Termination", "time forfeit");
+        loaded.set_tag("Round", "3");
+        let saved = loaded.updated(&Amazons::new_5x5());
+        assert_eq!(saved.tag("Termination"), None);
+        assert_eq!(saved.tag("Round"), Some("3"));
+        assert_eq!(saved.tag("Size"), Some("5"));
+    }
+}
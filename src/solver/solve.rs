@@ -0,0 +1,760 @@
+use super::*;
+use super::bitboard::*;
+
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::sync::mpsc::Sender;
+
+/// Most squares inside the border a board may have to be solved, so
+/// that a position fits the masks of a `Geometry::code`.
+pub const MAX_SOLVE_SQUARES: i8 = 25;
+
+/// Bits of each square mask in a `Geometry::code`.
+const CODE_MASK_BITS: u32 = MAX_SOLVE_SQUARES as u32;
+
+/// Positions with at least this many empty squares go in the
+/// database of a `Solver`; the many deeper ones are only cached.
+pub const DATABASE_EMPTY: u32 = 14;
+
+/// Most empty squares a position may have for `quick_to_solve`: the
+/// database of a `Solver` has the positions with more.
+pub const QUICK_SOLVE_EMPTY: u32 = DATABASE_EMPTY - 1;
+
+/// Log2 of the slots in each cache of `Solver::new`.
+pub const SOLVER_CACHE_BITS: u32 = 20;
+
+/// Bytes the caches of a `Solver` take for each slot.
+pub const SOLVER_SLOT_BYTES: u64 = 32;
+
+/// Positions a `Solver` searches between reports to `Solver::report_to`.
+const PROGRESS_NODES: u64 = 1 << 24;
+
+/// Positions a `Solver` looks at between checks of its `Solver::limit`.
+const POLL_NODES: u64 = 1 << 10;
+
+/// How a long solve is going.
+#[derive(Clone, Copy, Debug)]
+pub struct SolveProgress {
+    /// Positions searched so far.
+    pub nodes: u64,
+    /// Positions in the database so far.
+    pub solved: usize,
+}
+
+/// Whether `board` is small and full enough for a `Solver` to solve it
+/// in about a second.
+pub fn quick_to_solve(board: &Board) -> bool {
+    Geometry::new(board.board_size()).is_some_and(|geometry| {
+        Squares::from_board(board, &geometry).empty(&geometry).count_ones() <= QUICK_SOLVE_EMPTY
+    })
+}
+
+/// Solved positions: whether the team to move wins with perfect play,
+/// under the rule that a team left without a move loses.
+///
+/// Positions that are rotations or reflections of each other share an
+/// entry. A database saved from `Solver::database` after solving a
+/// position holds the positions with at least `DATABASE_EMPTY` empty
+/// squares on the way to proving its value, except below those settled
+/// by counting moves.
+#[derive(Clone, Debug, Default)]
+pub struct SolvedPositions {
+    wins: CodeMap<bool>,
+}
+
+impl SolvedPositions {
+    pub fn new() -> SolvedPositions {
+        SolvedPositions::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.wins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.wins.is_empty()
+    }
+
+    /// Whether `team` wins from `board` if it is to move, when known.
+    pub fn wins(&self, board: &Board, team: Team) -> Option<bool> {
+        let geometry = Geometry::new(board.board_size())?;
+        let code = geometry.code(&Squares::from_board(board, &geometry), team);
+        self.wins.get(&code).cloned()
+    }
+
+    /// The position after a move that keeps a won game won, if `board`
+    /// is known to be won for `team`.
+    pub fn winning_move(&self, board: &Board, team: Team) -> Option<Board> {
+        if self.wins(board, team) != Some(true) {
+            return None;
+        }
+        board.successors(team).find(|b| self.wins(b, team.other()) == Some(false))
+    }
+
+    /// The database as written to a file: each position as eight bytes,
+    /// little-endian, with the result in the top bit.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut codes: Vec<u64> = self.wins.iter()
+            .map(|(&code, &win)| code | (win as u64) << 63)
+            .collect();
+        codes.sort_unstable();
+        codes.iter().flat_map(|c| c.to_le_bytes()).collect()
+    }
+
+    /// Read a database written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SolvedPositions, String> {
+        if !bytes.len().is_multiple_of(8) {
+            return Err(format!("{} bytes is not a whole number of positions", bytes.len()));
+        }
+        let wins: CodeMap<bool> = bytes.chunks(8)
+            .map(|chunk| {
+                let mut word = [0; 8];
+                word.copy_from_slice(chunk);
+                let code = u64::from_le_bytes(word);
+                (code & !(1 << 63), code >> 63 == 1)
+            })
+            .collect();
+        // Every code starts with the side length of a solvable board.
+        let solvable = |n: u64| n >= 1 && n * n <= MAX_SOLVE_SQUARES as u64;
+        if let Some(code) = wins.keys().find(|&&code| !solvable(code >> (2 * CODE_MASK_BITS + 1))) {
+            return Err(format!("{:#x} is not a solved position", code));
+        }
+        Ok(SolvedPositions { wins })
+    }
+}
+
+/// Exhaustive search for the exact value of small positions.
+#[derive(Clone, Debug)]
+pub struct Solver {
+    solved: SolvedPositions,
+    /// Results for positions too deep for the database.
+    deep: Cache<bool>,
+    /// The most moves a team can make on its own, by `Geometry::code`
+    /// with the team to move.
+    moves_left: Cache<u32>,
+    /// The same counts by the shape of the region, for regions small
+    /// enough.
+    regions: RegionTable,
+    /// Positions searched so far, not counting those already solved.
+    pub nodes: u64,
+    /// Where to report every `PROGRESS_NODES` positions searched.
+    progress: Option<Sender<SolveProgress>>,
+    /// Set from another thread to give up.
+    stop: Option<Arc<AtomicBool>>,
+    /// When to give up.
+    deadline: Option<Instant>,
+    /// Positions and counts looked at, to check the limits now and then.
+    polls: u64,
+    /// Whether the solve in progress has given up. Results worked out
+    /// since are not stored.
+    gave_up: bool,
+}
+
+impl Default for Solver {
+    fn default() -> Solver {
+        Solver::with_cache_bits(SOLVER_CACHE_BITS)
+    }
+}
+
+impl Solver {
+    pub fn new() -> Solver {
+        Solver::default()
+    }
+
+    /// A solver whose caches have `2^bits` slots each. Bigger caches
+    /// solve large positions faster, at `SOLVER_SLOT_BYTES` a slot.
+    pub fn with_cache_bits(bits: u32) -> Solver {
+        Solver {
+            solved: SolvedPositions::new(),
+            deep: Cache::new(bits),
+            moves_left: Cache::new(bits),
+            regions: RegionTable::new(),
+            nodes: 0,
+            progress: None,
+            stop: None,
+            deadline: None,
+            polls: 0,
+            gave_up: false,
+        }
+    }
+
+    /// Report how the search is going to `progress` every so often.
+    pub fn report_to(&mut self, progress: Sender<SolveProgress>) {
+        self.progress = Some(progress);
+    }
+
+    /// Give up the solves that follow once `stop` is set or `deadline`
+    /// passes. What was solved before giving up is kept.
+    pub fn limit(&mut self, stop: Option<Arc<AtomicBool>>, deadline: Option<Instant>) {
+        self.stop = stop;
+        self.deadline = deadline;
+    }
+
+    /// Whether `team` to move wins from `board` with perfect play.
+    ///
+    /// Returns an error if the board has more than `MAX_SOLVE_SQUARES`
+    /// squares, or if the solve gives up at its `limit`.
+    pub fn solve(&mut self, board: &Board, team: Team) -> Result<bool, String> {
+        let n = board.board_size() - 2;
+        let geometry = Geometry::new(board.board_size())
+            .ok_or(format!("{}x{} is too big to solve, {} squares at most", n, n, MAX_SOLVE_SQUARES))?;
+        let squares = Squares::from_board(board, &geometry);
+        self.gave_up = false;
+        self.polls = 0;
+        let win = self.wins(&geometry, squares, team);
+        match self.gave_up {
+            true => Err(String::from("gave up before solving the position")),
+            false => Ok(win),
+        }
+    }
+
+    /// The position after a winning move for `team` from `board`, or
+    /// `None` if every move loses against best play.
+    pub fn winning_move(&mut self, board: &Board, team: Team) -> Result<Option<Board>, String> {
+        for next in board.successors(team) {
+            if !self.solve(&next, team.other())? {
+                return Ok(Some(next));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Every position solved so far with at least `DATABASE_EMPTY`
+    /// empty squares.
+    pub fn database(&self) -> &SolvedPositions {
+        &self.solved
+    }
+
+    fn known(&self, geometry: &Geometry, squares: &Squares, code: u64) -> Option<bool> {
+        match squares.empty(geometry).count_ones() >= DATABASE_EMPTY {
+            true => self.solved.wins.get(&code).cloned(),
+            false => self.deep.get(code),
+        }
+    }
+
+    /// Whether the solve should give up, looking at its limits only
+    /// every `POLL_NODES` calls.
+    fn giving_up(&mut self) -> bool {
+        if !self.gave_up && self.polls.is_multiple_of(POLL_NODES) {
+            self.gave_up = self.stop.as_ref().is_some_and(|s| s.load(Ordering::Relaxed))
+                || self.deadline.is_some_and(|d| Instant::now() >= d);
+        }
+        self.polls += 1;
+        self.gave_up
+    }
+
+    /// Whether `team` to move wins. Meaningless once `gave_up` is set.
+    fn wins(&mut self, geometry: &Geometry, squares: Squares, team: Team) -> bool {
+        let code = geometry.code(&squares, team);
+        if let Some(win) = self.known(geometry, &squares, code) {
+            return win;
+        }
+        if self.giving_up() {
+            return false;
+        }
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(PROGRESS_NODES) {
+            if let Some(progress) = &self.progress {
+                // Nobody listening is fine.
+                let _ = progress.send(SolveProgress { nodes: self.nodes, solved: self.solved.len() });
+            }
+        }
+
+        // Once neither team can reach the other, each plays alone, and
+        // the one that can make more moves wins.
+        let win = if geometry.separated(&squares) {
+            self.most_moves(geometry, squares, team) > self.most_moves(geometry, squares, team.other())
+        } else if self.private_moves(geometry, squares, team) > geometry.room(&squares, team.other()) {
+            // Every move fills an empty square, so a team can make no
+            // more moves than it can reach squares, and moves in regions
+            // only one team reaches cannot be taken away.
+            true
+        } else if self.private_moves(geometry, squares, team.other()) >= geometry.room(&squares, team) {
+            false
+        } else {
+            // Try the moves that leave the most territory first: a win
+            // is usually among them, and finding one ends the search.
+            // Late in the game, leaving the other team the fewest moves
+            // does better and is quicker to work out.
+            let late = squares.empty(geometry).count_ones() <= 10;
+            let mut moves = Vec::new();
+            let mut known_win = false;
+            geometry.for_each_move(&squares, team, &mut |next| {
+                let code = geometry.code(&next, team.other());
+                known_win = self.known(geometry, &next, code) == Some(false);
+                if known_win {
+                    return true;
+                }
+                let score = match late {
+                    true => -(geometry.mobility(&next, team.other()) as i32),
+                    false => geometry.territory(&next, team) * 4 - geometry.mobility(&next, team.other()) as i32,
+                };
+                moves.push((score, next));
+                false
+            });
+            moves.sort_unstable_by_key(|&(score, _)| std::cmp::Reverse(score));
+            known_win || moves.iter().any(|&(_, next)| !self.wins(geometry, next, team.other()))
+        };
+        if self.gave_up {
+            return false;
+        }
+        match squares.empty(geometry).count_ones() >= DATABASE_EMPTY {
+            true => { self.solved.wins.insert(code, win); },
+            false => self.deep.insert(code, win),
+        }
+        win
+    }
+
+    /// The longest sequence of moves `team` can make if the other team
+    /// never moves.
+    fn most_moves(&mut self, geometry: &Geometry, squares: Squares, team: Team) -> u32 {
+        let code = geometry.code(&squares, team);
+        if let Some(n) = self.moves_left.get(code) {
+            return n;
+        }
+        if self.giving_up() {
+            return 0;
+        }
+        let room = geometry.region(&squares, team) & squares.empty(geometry);
+        if let Some(n) = self.regions.moves_in(geometry.layout, team, room as u128, squares.pieces(team) as u128) {
+            self.moves_left.insert(code, n);
+            return n;
+        }
+        // Every move fills a square, so there are no more moves than
+        // squares the team can reach.
+        let bound = room.count_ones();
+        let mut most = 0;
+        geometry.for_each_move(&squares, team, &mut |next| {
+            most = most.max(1 + self.most_moves(geometry, next, team));
+            most == bound || self.gave_up
+        });
+        if !self.gave_up {
+            self.moves_left.insert(code, most);
+        }
+        most
+    }
+
+    /// The most moves `team` can make with its pieces in regions the
+    /// other team cannot reach.
+    fn private_moves(&mut self, geometry: &Geometry, squares: Squares, team: Team) -> u32 {
+        let theirs = squares.pieces(team.other());
+        let mut shut_in = Squares { red: 0, blue: 0, arrows: squares.arrows | theirs };
+        let mut pieces = squares.pieces(team);
+        while pieces != 0 {
+            let piece = pieces & pieces.wrapping_neg();
+            pieces &= pieces - 1;
+            if geometry.region_from(&squares, piece) & theirs == 0 {
+                match team {
+                    Team::Red => shut_in.red |= piece,
+                    Team::Blue => shut_in.blue |= piece,
+                }
+            } else {
+                shut_in.arrows |= piece;
+            }
+        }
+        match shut_in.pieces(team) {
+            0 => 0,
+            _ => self.most_moves(geometry, shut_in, team),
+        }
+    }
+}
+
+/// A position as masks of the squares inside the border, numbered from
+/// a1 along each rank.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Squares {
+    red: u32,
+    blue: u32,
+    arrows: u32,
+}
+
+impl Squares {
+    fn from_board(board: &Board, geometry: &Geometry) -> Squares {
+        let mut squares = Squares { red: 0, blue: 0, arrows: 0 };
+        for (i, &pos) in geometry.squares.iter().enumerate() {
+            match board.players().find(|p| p.pos == pos) {
+                Some(p) if p.team == Team::Red => squares.red |= 1 << i,
+                Some(_) => squares.blue |= 1 << i,
+                None if board.wall_at(pos) => squares.arrows |= 1 << i,
+                None => {},
+            }
+        }
+        squares
+    }
+
+    fn pieces(&self, team: Team) -> u32 {
+        match team {
+            Team::Red => self.red,
+            Team::Blue => self.blue,
+        }
+    }
+
+    fn filled(&self) -> u32 {
+        self.red | self.blue | self.arrows
+    }
+
+    fn empty(&self, geometry: &Geometry) -> u32 {
+        geometry.all & !self.filled()
+    }
+}
+
+/// What `Squares` needs to know about the shape of a board.
+struct Geometry {
+    /// The same numbering for `Board` masks.
+    layout: &'static Layout,
+    /// The square of each number.
+    squares: Vec<Pos>,
+    /// Every square's bit.
+    all: u32,
+    /// The squares in each of the eight directions from each square,
+    /// nearest first.
+    rays: Vec<[Vec<u8>; 8]>,
+    /// The squares next to each square.
+    neighbours: Vec<u32>,
+    /// For each symmetry, where it moves the squares of a mask, a byte
+    /// of the mask at a time.
+    permutations: Vec<[[u32; 256]; 4]>,
+}
+
+impl Geometry {
+    /// None if the board is too big for `Squares`.
+    fn new(board_size: i8) -> Option<Geometry> {
+        let n = board_size - 2;
+        if n < 1 || n * n > MAX_SOLVE_SQUARES {
+            return None;
+        }
+        let squares: Vec<Pos> = (1..=n).flat_map(|row| (1..=n).map(move |col| Pos { row, col })).collect();
+        let number = |p: Pos| ((p.row - 1) * n + p.col - 1) as u8;
+        let inside = |p: Pos| p.row >= 1 && p.row <= n && p.col >= 1 && p.col <= n;
+
+        let rays = squares.iter()
+            .map(|&from| {
+                let mut rays: [Vec<u8>; 8] = Default::default();
+                for (ray, &dir) in rays.iter_mut().zip(&QUEEN_DIRS) {
+                    *ray = (1..).map(|dist| from.with_offset(dir, dist))
+                        .take_while(|&p| inside(p))
+                        .map(number)
+                        .collect();
+                }
+                rays
+            })
+            .collect();
+        let neighbours = squares.iter()
+            .map(|&from| QUEEN_DIRS.iter()
+                .map(|&dir| from.with_offset(dir, 1))
+                .filter(|&p| inside(p))
+                .fold(0, |mask, p| mask | 1 << number(p)))
+            .collect();
+        let permutations = Symmetry::ALL.iter()
+            .map(|&sym| {
+                let mut table = [[0; 256]; 4];
+                for (byte, moved) in table.iter_mut().enumerate() {
+                    for (value, mask) in moved.iter_mut().enumerate() {
+                        *mask = squares.iter().enumerate()
+                            .filter(|&(i, _)| i / 8 == byte && value & 1 << (i % 8) != 0)
+                            .fold(0, |mask, (_, &p)| mask | 1 << number(p.transform(sym, board_size)));
+                    }
+                }
+                table
+            })
+            .collect();
+        Some(Geometry {
+            layout: Layout::of(board_size),
+            all: ((1u64 << squares.len()) - 1) as u32,
+            squares,
+            rays,
+            neighbours,
+            permutations,
+        })
+    }
+
+    /// The position's number, the same for its rotations and
+    /// reflections, with the side length of the board above the team
+    /// to move. Below them are two masks of `CODE_MASK_BITS`: the
+    /// squares with pieces, and those with Blue's pieces or arrows,
+    /// which between them tell what is on each square. It is the
+    /// smallest of the numbers of the position's transforms.
+    fn code(&self, squares: &Squares, team: Team) -> u64 {
+        let pieces = squares.red | squares.blue;
+        let blue_or_arrows = squares.blue | squares.arrows;
+        let permute = |table: &[[u32; 256]; 4], mask: u32| table.iter().enumerate()
+            .fold(0, |moved, (byte, t)| moved | t[(mask >> (8 * byte)) as usize & 0xff]) as u64;
+        let size = self.layout.n as u64;
+        let team = (size << 1 | (team == Team::Blue) as u64) << (2 * CODE_MASK_BITS);
+        self.permutations.iter()
+            .map(|table| team | permute(table, pieces) << CODE_MASK_BITS | permute(table, blue_or_arrows))
+            .min()
+            .unwrap()
+    }
+
+    /// Call `f` with the position after each move for `team`, until it
+    /// returns true.
+    fn for_each_move(&self, squares: &Squares, team: Team, f: &mut dyn FnMut(Squares) -> bool) {
+        let filled = squares.filled();
+        let mut pieces = squares.pieces(team);
+        while pieces != 0 {
+            let from = pieces.trailing_zeros() as usize;
+            pieces &= pieces - 1;
+            for ray in &self.rays[from] {
+                for &to in ray.iter().take_while(|&&to| filled & 1 << to == 0) {
+                    let moved = 1 << from | 1 << to;
+                    let after = filled ^ moved;
+                    for shots in &self.rays[to as usize] {
+                        for &shot in shots.iter().take_while(|&&shot| after & 1 << shot == 0) {
+                            let mut next = *squares;
+                            match team {
+                                Team::Red => next.red ^= moved,
+                                Team::Blue => next.blue ^= moved,
+                            }
+                            next.arrows |= 1 << shot;
+                            if f(next) {
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The squares connected to `team`'s pieces by steps in any of the
+    /// eight directions through empty squares, with any pieces they touch.
+    fn region(&self, squares: &Squares, team: Team) -> u32 {
+        self.region_from(squares, squares.pieces(team))
+    }
+
+    /// Like `region`, for the pieces in `start`.
+    fn region_from(&self, squares: &Squares, start: u32) -> u32 {
+        let empty = squares.empty(self);
+        let open = empty | squares.red | squares.blue;
+        let mut region = start;
+        let mut frontier = region;
+        while frontier != 0 {
+            let mut grown = 0;
+            while frontier != 0 {
+                grown |= self.neighbours[frontier.trailing_zeros() as usize];
+                frontier &= frontier - 1;
+            }
+            grown &= open & !region;
+            region |= grown;
+            frontier = grown & empty;
+        }
+        region
+    }
+
+    /// Empty squares `team` reaches in fewer queen moves than the other
+    /// team, less those the other team reaches first.
+    fn territory(&self, squares: &Squares, team: Team) -> i32 {
+        let mine = self.distances(squares, team);
+        let theirs = self.distances(squares, team.other());
+        let (mut my_seen, mut their_seen) = (0, 0);
+        let mut score = 0;
+        for d in 0..mine.len().max(theirs.len()) {
+            let a = mine.get(d).cloned().unwrap_or(0);
+            let b = theirs.get(d).cloned().unwrap_or(0);
+            score += (a & !b & !their_seen).count_ones() as i32 - (b & !a & !my_seen).count_ones() as i32;
+            my_seen |= a;
+            their_seen |= b;
+        }
+        score
+    }
+
+    /// The empty squares first reached by `team`'s pieces after each
+    /// number of queen moves, starting from one.
+    fn distances(&self, squares: &Squares, team: Team) -> Vec<u32> {
+        let filled = squares.filled();
+        let mut reached = 0;
+        let mut frontier = squares.pieces(team);
+        let mut layers = Vec::new();
+        while frontier != 0 {
+            let mut next = 0;
+            let mut from = frontier;
+            while from != 0 {
+                for ray in &self.rays[from.trailing_zeros() as usize] {
+                    for &to in ray.iter().take_while(|&&to| filled & 1 << to == 0) {
+                        next |= 1 << to;
+                    }
+                }
+                from &= from - 1;
+            }
+            frontier = next & !reached;
+            reached |= frontier;
+            layers.push(frontier);
+        }
+        layers
+    }
+
+    /// Number of squares `team`'s pieces can move to.
+    fn mobility(&self, squares: &Squares, team: Team) -> u32 {
+        let filled = squares.filled();
+        let mut n = 0;
+        let mut pieces = squares.pieces(team);
+        while pieces != 0 {
+            let from = pieces.trailing_zeros() as usize;
+            pieces &= pieces - 1;
+            for ray in &self.rays[from] {
+                n += ray.iter().take_while(|&&to| filled & 1 << to == 0).count() as u32;
+            }
+        }
+        n
+    }
+
+    /// Number of empty squares `team` can reach.
+    fn room(&self, squares: &Squares, team: Team) -> u32 {
+        (self.region(squares, team) & squares.empty(self)).count_ones()
+    }
+
+    /// Whether no empty square is reachable by both teams, so that
+    /// neither can ever get in the other's way.
+    fn separated(&self, squares: &Squares) -> bool {
+        self.region(squares, Team::Red) & squares.blue == 0
+    }
+}
+
+/// Positions by their codes, which need only a multiply to hash well.
+type CodeMap<V> = HashMap<u64, V, BuildHasherDefault<CodeHasher>>;
+
+#[derive(Default)]
+struct CodeHasher(u64);
+
+impl Hasher for CodeHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 << 8 | b as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        }
+    }
+
+    fn write_u64(&mut self, code: u64) {
+        self.0 = code.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+}
+
+/// A fixed number of results by position code, each slot keeping the
+/// latest position stored in it.
+#[derive(Clone, Debug)]
+struct Cache<V> {
+    bits: u32,
+    /// Codes with the top bit set, so that an empty slot is zero.
+    slots: Vec<(u64, V)>,
+}
+
+impl<V: Copy + Default> Cache<V> {
+    fn new(bits: u32) -> Cache<V> {
+        Cache { bits, slots: vec![(0, V::default()); 1 << bits] }
+    }
+
+    fn slot(&self, code: u64) -> usize {
+        (code.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (64 - self.bits)) as usize
+    }
+
+    fn get(&self, code: u64) -> Option<V> {
+        let (stored, value) = self.slots[self.slot(code)];
+        match stored == code | 1 << 63 {
+            true => Some(value),
+            false => None,
+        }
+    }
+
+    fn insert(&mut self, code: u64, value: V) {
+        let slot = self.slot(code);
+        self.slots[slot] = (code | 1 << 63, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(fen: &str) -> Board {
+        Amazons::from_fen(fen).unwrap().curr_board().clone()
+    }
+
+    fn code(board: &Board, team: Team) -> u64 {
+        let geometry = Geometry::new(board.board_size()).unwrap();
+        geometry.code(&Squares::from_board(board, &geometry), team)
+    }
+
+    #[test]
+    fn code_is_the_same_for_every_symmetry() {
+        let board = board("3R1/1B2#/2##1/2BR#/5 r");
+        let red = code(&board, Team::Red);
+        for &sym in Symmetry::ALL.iter() {
+            assert_eq!(code(&board.transform(sym), Team::Red), red, "{:?}", sym);
+        }
+        assert_ne!(code(&board, Team::Blue), red);
+        // Swapping a piece for an arrow is another position.
+        assert_ne!(code(&self::board("3R1/1B2#/2##1/2#R#/5 r"), Team::Red), red);
+    }
+
+    #[test]
+    fn database_round_trips_through_bytes() {
+        let mut db = SolvedPositions::new();
+        let positions = [("3R1/1B2#/2##1/2BR#/5 r", Team::Red, true), ("R3B/5/5/5/B3R b", Team::Blue, false)];
+        for &(fen, team, win) in positions.iter() {
+            db.wins.insert(code(&board(fen), team), win);
+        }
+        let bytes = db.to_bytes();
+        assert_eq!(bytes.len(), 16);
+        let read = SolvedPositions::from_bytes(&bytes).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read.to_bytes(), bytes);
+        for &(fen, team, win) in positions.iter() {
+            assert_eq!(read.wins(&board(fen).transform(Symmetry::Rotate90), team), Some(win));
+        }
+        assert_eq!(read.wins(&board("3R1/1B2#/2##1/2BR#/5 r"), Team::Blue), None);
+        assert!(SolvedPositions::from_bytes(&bytes[..12]).is_err());
+        assert!(SolvedPositions::from_bytes(&[0; 8]).is_err());
+    }
+
+    #[test]
+    fn database_knows_nothing_of_other_board_sizes() {
+        // The squares of both are numbered the same from the corner.
+        let five = board("5/5/5/5/RB3 b");
+        let four = board("4/4/4/RB2 b");
+        let mut db = SolvedPositions::new();
+        db.wins.insert(code(&five, Team::Blue), true);
+        let read = SolvedPositions::from_bytes(&db.to_bytes()).unwrap();
+        assert_eq!(read.wins(&five, Team::Blue), Some(true));
+        assert_eq!(read.wins(&four, Team::Blue), None);
+        assert_eq!(read.winning_move(&four, Team::Blue), None);
+    }
+
+    /// A 5x5 game eight moves in, with 13 empty squares.
+    fn midgame() -> Board {
+        let mut board = Amazons::new_5x5().curr_board().clone();
+        let mut team = Team::Red;
+        for ply in 0..8 {
+            let next: Vec<Board> = board.successors(team).collect();
+            board = next[(ply * 31 + 5) % next.len()].clone();
+            team = team.other();
+        }
+        board
+    }
+
+    #[test]
+    fn solver_gives_up_at_its_limits_and_keeps_going_after() {
+        let board = midgame();
+        let mut solver = Solver::with_cache_bits(16);
+        solver.limit(Some(Arc::new(AtomicBool::new(true))), None);
+        assert!(solver.solve(&board, Team::Red).is_err());
+        assert!(solver.winning_move(&board, Team::Red).is_err());
+        solver.limit(None, Some(Instant::now()));
+        assert!(solver.solve(&board, Team::Red).is_err());
+
+        solver.limit(None, None);
+        let win = solver.solve(&board, Team::Red).unwrap();
+        assert_eq!(Solver::with_cache_bits(16).solve(&board, Team::Red), Ok(win));
+    }
+
+    #[test]
+    fn solver_counts_moves_once_separated() {
+        // Red has the corridor along the top, and Blue is walled in.
+        let board = board("R2##/####B/#####/#####/##### b");
+        assert_eq!(Solver::with_cache_bits(10).solve(&board, Team::Blue), Ok(false));
+        assert_eq!(Solver::with_cache_bits(10).solve(&board, Team::Red), Ok(true));
+    }
+}
@@ -0,0 +1,161 @@
+use super::board::*;
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Positions the table holds before it starts over.
+pub const TABLE_CAPACITY: usize = 1 << 15;
+
+/// What a search found from one position.
+#[derive(Clone, Debug)]
+struct Entry {
+    depth: i32,
+    score: i64,
+    /// The best line, on the canonical board.
+    line: Vec<Move>,
+}
+
+/// Search results by position, so that a position searched once, in
+/// this search or an earlier one such as a ponder, is not searched
+/// again.
+///
+/// Positions that are rotations or reflections of each other share an
+/// entry.
+#[derive(Clone, Debug, Default)]
+pub struct TranspositionTable {
+    entries: HashMap<u64, Entry>,
+}
+
+impl TranspositionTable {
+    pub fn new() -> TranspositionTable {
+        TranspositionTable::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The best line and score for `team` from `board`, if it was
+    /// searched at least `depth` moves deep. `canonical` is the board's
+    /// `canonical_key`.
+    pub fn get(&self, board: &Board, canonical: (u64, Symmetry), team: Team, strategy: EvalStrategy,
+               depth: i32) -> Option<(Vec<Board>, i64)> {
+        let (position, sym) = canonical;
+        let entry = self.entries.get(&key(position, team, strategy)).filter(|e| e.depth >= depth)?;
+        let mut line = Vec::with_capacity(entry.line.len());
+        let mut at = board.clone();
+        for mv in &entry.line {
+            at = at.play(&mv.transform(sym.inverse(), board.board_size()))?;
+            line.push(at.clone());
+        }
+        Some((line, entry.score))
+    }
+
+    /// Remember a search's line and score, unless a deeper one is known.
+    pub fn insert(&mut self, board: &Board, canonical: (u64, Symmetry), team: Team, strategy: EvalStrategy,
+                  depth: i32, (line, score): (&[Board], i64)) {
+        let (position, sym) = canonical;
+        let key = key(position, team, strategy);
+        if self.entries.get(&key).is_some_and(|e| e.depth > depth) {
+            return;
+        }
+        if self.entries.len() >= TABLE_CAPACITY {
+            self.entries.clear();
+        }
+        let line = std::iter::once(board).chain(line).zip(line)
+            .flat_map(|(a, b)| a.diff_move(b))
+            .map(|mv| mv.transform(sym, board.board_size()))
+            .collect();
+        self.entries.insert(key, Entry { depth, score, line });
+    }
+}
+
+fn key(position: u64, team: Team, strategy: EvalStrategy) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    position.hash(&mut hasher);
+    team.hash(&mut hasher);
+    strategy.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::Amazons;
+
+    /// A lopsided position on the 8x8 board, and a line of two moves
+    /// from it.
+    fn position() -> (Board, Vec<Board>) {
+        let start = Amazons::new_8x8().curr_board().clone();
+        let board = start.successors(Team::Red).nth(40).unwrap();
+        let reply = board.successors(Team::Blue).nth(7).unwrap();
+        let answer = reply.successors(Team::Red).nth(3).unwrap();
+        (board, vec![reply, answer])
+    }
+
+    #[test]
+    fn stored_lines_come_back() {
+        let (board, line) = position();
+        let canonical = board.canonical_key();
+        let mut table = TranspositionTable::new();
+        table.insert(&board, canonical, Team::Blue, EvalStrategy::QueenDistance, 2, (&line, 17));
+        assert_eq!(table.get(&board, canonical, Team::Blue, EvalStrategy::QueenDistance, 2), Some((line.clone(), 17)));
+        assert_eq!(table.get(&board, canonical, Team::Blue, EvalStrategy::QueenDistance, 1), Some((line, 17)));
+        assert_eq!(table.get(&board, canonical, Team::Blue, EvalStrategy::QueenDistance, 3), None);
+        assert_eq!(table.get(&board, canonical, Team::Red, EvalStrategy::QueenDistance, 2), None);
+        assert_eq!(table.get(&board, canonical, Team::Blue, EvalStrategy::KingDistance, 2), None);
+    }
+
+    #[test]
+    fn symmetric_positions_share_an_entry() {
+        let (board, line) = position();
+        let mut table = TranspositionTable::new();
+        table.insert(&board, board.canonical_key(), Team::Blue, EvalStrategy::QueenDistance, 2, (&line, 17));
+        for &sym in Symmetry::ALL.iter() {
+            let moved = board.transform(sym);
+            let (found, score) = table.get(&moved, moved.canonical_key(), Team::Blue, EvalStrategy::QueenDistance, 2)
+                .unwrap();
+            assert_eq!(score, 17);
+            let keys: Vec<u64> = found.iter().map(|b| b.key()).collect();
+            let expected: Vec<u64> = line.iter().map(|b| b.transform(sym).key()).collect();
+            assert_eq!(keys, expected, "{:?}", sym);
+        }
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn deeper_results_are_kept() {
+        let (board, line) = position();
+        let canonical = board.canonical_key();
+        let mut table = TranspositionTable::new();
+        table.insert(&board, canonical, Team::Blue, EvalStrategy::QueenDistance, 3, (&line, 17));
+        table.insert(&board, canonical, Team::Blue, EvalStrategy::QueenDistance, 2, (&line[..1], -4));
+        assert_eq!(table.get(&board, canonical, Team::Blue, EvalStrategy::QueenDistance, 1), Some((line.clone(), 17)));
+        table.insert(&board, canonical, Team::Blue, EvalStrategy::QueenDistance, 4, (&line[..1], -4));
+        assert_eq!(table.get(&board, canonical, Team::Blue, EvalStrategy::QueenDistance, 4), Some((line[..1].to_vec(), -4)));
+    }
+
+    #[test]
+    fn full_table_starts_over() {
+        let (board, line) = position();
+        let mut table = TranspositionTable::new();
+        for position in 0..TABLE_CAPACITY as u64 {
+            table.insert(&board, (position, Symmetry::Identity), Team::Red, EvalStrategy::QueenDistance, 1, (&[], 0));
+        }
+        assert_eq!(table.len(), TABLE_CAPACITY);
+        let canonical = board.canonical_key();
+        table.insert(&board, canonical, Team::Blue, EvalStrategy::QueenDistance, 2, (&line, 17));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.get(&board, (0, Symmetry::Identity), Team::Red, EvalStrategy::QueenDistance, 1), None);
+        assert_eq!(table.get(&board, canonical, Team::Blue, EvalStrategy::QueenDistance, 2), Some((line, 17)));
+    }
+}
@@ -0,0 +1,383 @@
+use super::board::*;
+
+/// One position in the tree.
+#[derive(Clone, Debug)]
+struct Node {
+    board: Board,
+    /// The move from the parent, None at the root.
+    mv: Option<Move>,
+    parent: Option<usize>,
+    /// Continuations, the main line first.
+    children: Vec<usize>,
+    ply: usize,
+    name: Option<String>,
+    notes: Notes,
+}
+
+/// What a game record says about a move besides the move itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Notes {
+    /// A remark such as `?!`, `??` or `!`.
+    pub glyph: Option<String>,
+    /// Text comments after the move.
+    pub comments: Vec<String>,
+}
+
+/// Every position reached in a game, variations included, and a
+/// cursor at the position being looked at.
+///
+/// Going back never forgets anything: playing a move that differs from
+/// the one played before starts a new variation next to it.
+#[derive(Clone, Debug)]
+pub struct GameTree {
+    nodes: Vec<Node>,
+    cursor: usize,
+}
+
+impl GameTree {
+    pub fn new(root: Board) -> GameTree {
+        GameTree {
+            nodes: vec![Node {
+                board: root,
+                mv: None,
+                parent: None,
+                children: Vec::new(),
+                ply: 0,
+                name: None,
+                notes: Notes::default(),
+            }],
+            cursor: 0,
+        }
+    }
+
+    pub fn root(&self) -> &Board {
+        &self.nodes[0].board
+    }
+
+    pub fn current(&self) -> &Board {
+        &self.nodes[self.cursor].board
+    }
+
+    /// Which position the cursor is at, to tell whether it has moved.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Moves from the root to the cursor.
+    pub fn ply(&self) -> usize {
+        self.nodes[self.cursor].ply
+    }
+
+    /// The move that led to the cursor.
+    pub fn last_move(&self) -> Option<Move> {
+        self.nodes[self.cursor].mv.clone()
+    }
+
+    /// The board `i` moves before the cursor, or the root if the line
+    /// is not that long.
+    pub fn nth_back(&self, i: usize) -> &Board {
+        let mut id = self.cursor;
+        for _ in 0..i {
+            match self.nodes[id].parent {
+                Some(p) => id = p,
+                None => break,
+            }
+        }
+        &self.nodes[id].board
+    }
+
+    /// The last position of the main line, with its ply.
+    pub fn main_end(&self) -> (usize, &Board) {
+        let mut id = 0;
+        while let Some(&c) = self.nodes[id].children.first() {
+            id = c;
+        }
+        (self.nodes[id].ply, &self.nodes[id].board)
+    }
+
+    /// The moves from the root to the cursor, oldest first.
+    pub fn line(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        let mut id = self.cursor;
+        while let Some(p) = self.nodes[id].parent {
+            moves.extend(self.nodes[id].mv.clone());
+            id = p;
+        }
+        moves.reverse();
+        moves
+    }
+
+    /// Move the cursor to `board`, which must be one move after the
+    /// current board. Reuses a continuation with the same move.
+    pub fn play(&mut self, board: Board) {
+        let mv = self.current().diff_move(&board);
+        if let Some(&child) = self.nodes[self.cursor].children.iter()
+            .find(|&&c| self.nodes[c].mv == mv) {
+            self.cursor = child;
+            return;
+        }
+
+        let id = self.nodes.len();
+        let ply = self.ply() + 1;
+        self.nodes.push(Node {
+            board,
+            mv,
+            parent: Some(self.cursor),
+            children: Vec::new(),
+            ply,
+            name: None,
+            notes: Notes::default(),
+        });
+        self.nodes[self.cursor].children.push(id);
+        self.cursor = id;
+    }
+
+    /// Step up to `n` moves towards the root, returning how many were taken.
+    pub fn back(&mut self, n: usize) -> usize {
+        let mut taken = 0;
+        while taken < n {
+            match self.nodes[self.cursor].parent {
+                Some(p) => self.cursor = p,
+                None => break,
+            }
+            taken += 1;
+        }
+        taken
+    }
+
+    /// Step up to `n` moves along the main continuation, returning how
+    /// many were taken.
+    pub fn forward(&mut self, n: usize) -> usize {
+        let mut taken = 0;
+        while taken < n {
+            match self.nodes[self.cursor].children.first() {
+                Some(&c) => self.cursor = c,
+                None => break,
+            }
+            taken += 1;
+        }
+        taken
+    }
+
+    /// Go to a ply of the current line, continuing along the main line
+    /// past the cursor. Returns false, leaving the cursor where it was,
+    /// if the line is too short.
+    pub fn jump(&mut self, ply: usize) -> bool {
+        let here = self.ply();
+        if ply <= here {
+            self.back(here - ply);
+            return true;
+        }
+        let start = self.cursor;
+        if self.forward(ply - here) < ply - here {
+            self.cursor = start;
+            return false;
+        }
+        true
+    }
+
+    /// Moves that have been played from the cursor, the main line first.
+    pub fn continuations(&self) -> Vec<Move> {
+        self.nodes[self.cursor].children.iter()
+            .flat_map(|&c| self.nodes[c].mv.clone())
+            .collect()
+    }
+
+    /// Follow the `i`th continuation. Returns false if there is none.
+    pub fn enter(&mut self, i: usize) -> bool {
+        match self.nodes[self.cursor].children.get(i) {
+            Some(&c) => {
+                self.cursor = c;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Make the line leading to the cursor the main line.
+    pub fn promote(&mut self) {
+        let mut id = self.cursor;
+        while let Some(p) = self.nodes[id].parent {
+            let children = &mut self.nodes[p].children;
+            if let Some(i) = children.iter().position(|&c| c == id) {
+                let c = children.remove(i);
+                children.insert(0, c);
+            }
+            id = p;
+        }
+    }
+
+    /// Name the position at the cursor, so it can be found with `goto`.
+    pub fn set_name(&mut self, name: &str) {
+        for node in &mut self.nodes {
+            if node.name.as_deref() == Some(name) {
+                node.name = None;
+            }
+        }
+        self.nodes[self.cursor].name = Some(name.to_string());
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.nodes[self.cursor].name.as_deref()
+    }
+
+    /// Remarks on the move that led to the cursor.
+    pub fn notes(&self) -> &Notes {
+        &self.nodes[self.cursor].notes
+    }
+
+    pub fn set_notes(&mut self, notes: Notes) {
+        self.nodes[self.cursor].notes = notes;
+    }
+
+    /// Move the cursor to a named position. Returns false if no
+    /// position has that name.
+    pub fn goto(&mut self, name: &str) -> bool {
+        match self.nodes.iter().position(|n| n.name.as_deref() == Some(name)) {
+            Some(id) => {
+                self.cursor = id;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Every name, with the ply of its position.
+    pub fn names(&self) -> Vec<(String, usize)> {
+        self.nodes.iter()
+            .filter_map(|n| n.name.clone().map(|name| (name, n.ply)))
+            .collect()
+    }
+
+    /// Walk the whole tree from the root, in the order a game record
+    /// lists it: each move, then any variations that replace that move,
+    /// each between `Enter` and `Leave`.
+    pub fn walk(&self, visit: &mut dyn FnMut(TreeStep)) {
+        self.walk_from(0, visit);
+    }
+
+    fn walk_from(&self, id: usize, visit: &mut dyn FnMut(TreeStep)) {
+        let mut id = id;
+        while let Some((&main, others)) = self.nodes[id].children.split_first() {
+            visit(self.step(main));
+            for &other in others {
+                visit(TreeStep::Enter);
+                visit(self.step(other));
+                self.walk_from(other, visit);
+                visit(TreeStep::Leave);
+            }
+            id = main;
+        }
+    }
+
+    fn step(&self, id: usize) -> TreeStep {
+        let node = &self.nodes[id];
+        TreeStep::Move(node.ply, node.mv.clone().unwrap(), node.name.clone(), node.notes.clone())
+    }
+}
+
+/// What `GameTree::walk` passes by.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TreeStep {
+    /// A move, with the ply it reaches, the name of that position and
+    /// the remarks on the move.
+    Move(usize, Move, Option<String>, Notes),
+    /// A variation starts; it replaces the move seen just before.
+    Enter,
+    /// The innermost variation ends.
+    Leave,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::Amazons;
+
+    /// A tree with a main line of `plies` moves from the 5x5 start, and
+    /// the boards along it, the root first.
+    fn main_line(plies: usize) -> (GameTree, Vec<Board>) {
+        let mut board = Amazons::new_5x5().curr_board().clone();
+        let mut tree = GameTree::new(board.clone());
+        let mut boards = vec![board.clone()];
+        let mut team = Team::Red;
+        for _ in 0..plies {
+            let next = board.successors(team).next().unwrap();
+            board = next;
+            tree.play(board.clone());
+            boards.push(board.clone());
+            team = team.other();
+        }
+        (tree, boards)
+    }
+
+    #[test]
+    fn back_and_forward_walk_the_line() {
+        let (mut tree, boards) = main_line(4);
+        assert_eq!(tree.ply(), 4);
+        assert_eq!(tree.back(10), 4);
+        assert_eq!(*tree.current(), boards[0]);
+        assert_eq!(tree.forward(2), 2);
+        assert_eq!(*tree.current(), boards[2]);
+        assert_eq!(*tree.nth_back(1), boards[1]);
+        assert!(!tree.jump(5));
+        assert_eq!(tree.ply(), 2);
+        assert!(tree.jump(4));
+        assert_eq!(tree.line().len(), 4);
+        assert_eq!(tree.main_end(), (4, &boards[4]));
+    }
+
+    #[test]
+    fn another_move_starts_a_variation() {
+        let (mut tree, boards) = main_line(3);
+        tree.back(2);
+        let other = boards[1].successors(Team::Blue).nth(5).unwrap();
+        tree.play(other.clone());
+        assert_eq!(tree.ply(), 2);
+        assert_eq!(tree.main_end(), (3, &boards[3]));
+
+        tree.back(1);
+        let continuations = tree.continuations();
+        assert_eq!(continuations.len(), 2);
+        assert_eq!(Some(&continuations[1]), boards[1].diff_move(&other).as_ref());
+        // Playing a move again follows the existing continuation.
+        tree.play(boards[2].clone());
+        tree.back(1);
+        assert_eq!(tree.continuations().len(), 2);
+
+        assert!(tree.enter(1));
+        tree.promote();
+        assert_eq!(tree.main_end(), (2, &other));
+        tree.back(1);
+        assert!(!tree.enter(2));
+    }
+
+    #[test]
+    fn names_find_positions() {
+        let (mut tree, boards) = main_line(3);
+        tree.back(1);
+        tree.set_name("here");
+        assert_eq!(tree.name(), Some("here"));
+        tree.back(2);
+        assert!(tree.goto("here"));
+        assert_eq!(*tree.current(), boards[2]);
+        assert!(!tree.goto("nowhere"));
+        tree.forward(1);
+        tree.set_name("here");
+        assert_eq!(tree.names(), vec![(String::from("here"), 3)]);
+    }
+
+    #[test]
+    fn walk_lists_variations_after_the_move_they_replace() {
+        let (mut tree, boards) = main_line(2);
+        tree.back(1);
+        let other = boards[1].successors(Team::Blue).nth(3).unwrap();
+        tree.play(other);
+        let mut steps = Vec::new();
+        tree.walk(&mut |step| steps.push(match step {
+            TreeStep::Move(ply, _, _, _) => ply.to_string(),
+            TreeStep::Enter => String::from("("),
+            TreeStep::Leave => String::from(")"),
+        }));
+        assert_eq!(steps.join(" "), "1 2 ( 2 )");
+    }
+}
@@ -0,0 +1,442 @@
+use crate::{Player, redo_turn, takeback_plies};
+use crate::render::*;
+use crate::solver::*;
+use crate::solver::board::*;
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use termion::{clear, color, cursor};
+use termion::event::{Event, Key, MouseButton, MouseEvent};
+use termion::input::{MouseTerminal, TermRead};
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+
+/// How often to look for input, and to redraw while clocks run.
+const POLL: Duration = Duration::from_millis(30);
+const REDRAW: Duration = Duration::from_millis(200);
+
+/// Screen position of the board's top left corner.
+const TOP: u16 = 2;
+const LEFT: u16 = 3;
+
+const HELP: [&str; 10] = [
+    "arrows/mouse  move cursor",
+    "enter/space   select square",
+    "esc           cancel selection",
+    "a             let the AI move",
+    "m             move or show hints now",
+    "h             show the best moves",
+    "u             undo",
+    "r             redo",
+    "t             toggle territory",
+    "q             quit",
+];
+
+/// What the next selection picks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    Piece,
+    Destination(Pos),
+    Shot(Pos, Pos),
+}
+
+struct Tui {
+    amazons: Amazons,
+    players: HashMap<Team, Player>,
+    cursor: Pos,
+    stage: Stage,
+    message: String,
+    show_territory: bool,
+    /// Suggested moves, and the ply and last move of the position they
+    /// were found for.
+    hints: Vec<Hint>,
+    hints_for: (usize, Option<Move>),
+    /// The search for hints running, with the ply and last move of the
+    /// position it started from.
+    hinting: Option<((usize, Option<Move>), HintHandle)>,
+    /// The search running for a team, if any.
+    thinking: Option<(Team, SearchHandle)>,
+    ponder: bool,
+    /// The AI's search during a human's turn, with the ply and last
+    /// move of the position it started from.
+    pondering: Option<((usize, Option<Move>), SearchHandle)>,
+    /// The reply the AI expected after its last move.
+    predicted: Option<Move>,
+    style: Style,
+}
+
+/// Play a game in a raw-mode full-screen terminal until it ends or
+/// the user quits.
+///
+/// With `ponder`, an AI keeps thinking while its human opponent does.
+pub fn run(amazons: Amazons, players: &HashMap<Team, Player>, style: Style, ponder: bool) -> io::Result<()> {
+    let mut screen = AlternateScreen::from(MouseTerminal::from(io::stdout().into_raw_mode()?));
+    write!(screen, "{}", cursor::Hide)?;
+
+    let mut tui = Tui {
+        amazons,
+        players: players.clone(),
+        cursor: Pos { row: 1, col: 1 },
+        stage: Stage::Piece,
+        message: String::new(),
+        show_territory: false,
+        hints: Vec::new(),
+        hints_for: (0, None),
+        hinting: None,
+        thinking: None,
+        ponder,
+        pondering: None,
+        predicted: None,
+        style,
+    };
+    tui.step_ai();
+    tui.draw(&mut screen)?;
+    let mut drawn = Instant::now();
+
+    // Input is polled so that searches and clocks go on meanwhile.
+    let mut events = termion::async_stdin().events();
+    loop {
+        let event = match events.next() {
+            Some(event) => event?,
+            None => {
+                let ai_changed = tui.step_ai() | tui.step_hints();
+                let ticking = tui.thinking.is_some() || tui.hinting.is_some() || tui.amazons.clock().is_some();
+                if ai_changed || (ticking && drawn.elapsed() >= REDRAW) {
+                    tui.draw(&mut screen)?;
+                    drawn = Instant::now();
+                }
+                thread::sleep(POLL);
+                continue;
+            },
+        };
+        match event {
+            Event::Key(Key::Char('q')) | Event::Key(Key::Ctrl('c')) => break,
+            Event::Key(Key::Up) => tui.shift_cursor(1, 0),
+            Event::Key(Key::Down) => tui.shift_cursor(-1, 0),
+            Event::Key(Key::Left) => tui.shift_cursor(0, -1),
+            Event::Key(Key::Right) => tui.shift_cursor(0, 1),
+            Event::Key(Key::Char('\n')) | Event::Key(Key::Char(' ')) => tui.select(),
+            Event::Key(Key::Esc) => tui.stage = Stage::Piece,
+            Event::Key(Key::Char('m')) => {
+                if let Some((_, handle)) = &tui.thinking {
+                    handle.stop();
+                    tui.message = String::from("Moving now...");
+                } else if let Some((_, handle)) = &tui.hinting {
+                    handle.stop();
+                }
+            },
+            Event::Key(Key::Char('u')) => {
+                tui.cancel_search();
+                let n = takeback_plies(&tui.amazons, &tui.players);
+                tui.message = match tui.amazons.undo(n) {
+                    0 => String::from("Nothing to undo"),
+                    undone => format!("Took back {} moves", undone),
+                };
+                tui.stage = Stage::Piece;
+            },
+            Event::Key(Key::Char('r')) => {
+                tui.cancel_search();
+                tui.message = match redo_turn(&mut tui.amazons, &tui.players) {
+                    0 => String::from("Nothing to redo"),
+                    redone => format!("Replayed {} moves", redone),
+                };
+                tui.stage = Stage::Piece;
+            },
+            Event::Key(Key::Char('t')) => tui.show_territory = !tui.show_territory,
+            Event::Key(Key::Char('h')) if !tui.game_over() && tui.thinking.is_none() && tui.hinting.is_none() => {
+                // Pondering would only slow the hints down.
+                tui.stop_pondering();
+                let here = (tui.amazons.ply(), tui.amazons.last_move());
+                tui.hinting = Some((here, tui.amazons.start_hints(3, EvalStrategy::QueenDistance)));
+                tui.message = String::from("Looking for hints, press m to show them now...");
+            },
+            Event::Key(Key::Char('a')) if !tui.game_over() && tui.thinking.is_none() => {
+                tui.stop_pondering();
+                tui.stop_hints();
+                let team = tui.amazons.to_move();
+                tui.thinking = Some((team, tui.amazons.start_search(team, EvalStrategy::QueenDistance)));
+                tui.message = format!("{:?} is thinking...", team);
+                tui.stage = Stage::Piece;
+            },
+            Event::Mouse(MouseEvent::Press(MouseButton::Left, x, y)) => {
+                if let Some(pos) = tui.screen_to_pos(x, y) {
+                    tui.cursor = pos;
+                    tui.select();
+                }
+            },
+            _ => {},
+        }
+        tui.step_ai();
+        tui.step_hints();
+        tui.draw(&mut screen)?;
+        drawn = Instant::now();
+    }
+
+    tui.cancel_search();
+    tui.stop_pondering();
+    tui.stop_hints();
+    write!(screen, "{}", cursor::Show)?;
+    screen.flush()
+}
+
+impl Tui {
+    fn size(&self) -> i8 {
+        self.amazons.board_size()
+    }
+
+    fn game_over(&self) -> bool {
+        self.amazons.winner().is_some()
+    }
+
+    fn shift_cursor(&mut self, dr: i8, dc: i8) {
+        let max = self.size() - 2;
+        self.cursor.row = (self.cursor.row + dr).max(1).min(max);
+        self.cursor.col = (self.cursor.col + dc).max(1).min(max);
+    }
+
+    fn screen_to_pos(&self, x: u16, y: u16) -> Option<Pos> {
+        if x < LEFT + LABEL_WIDTH || y < TOP + 1 {
+            return None;
+        }
+        // Ranks are drawn highest first, below the file labels.
+        let (line, file) = (y - TOP, (x - LEFT - LABEL_WIDTH) / 2 + 1);
+        let max = self.size() as u16 - 2;
+        if line <= max && file <= max {
+            Some(Pos { row: self.size() - 1 - line as i8, col: file as i8 })
+        } else {
+            None
+        }
+    }
+
+    /// Squares the current stage lets the player pick.
+    fn choices(&self) -> Vec<Pos> {
+        let board = self.amazons.curr_board();
+        match self.stage {
+            Stage::Piece => Vec::new(),
+            Stage::Destination(from) => queen_range(board, from, from).collect(),
+            Stage::Shot(from, to) => queen_range(board, to, from).collect(),
+        }
+    }
+
+    fn select(&mut self) {
+        if self.game_over() || self.players[&self.amazons.to_move()] != Player::Human {
+            return;
+        }
+        if self.thinking.is_some() {
+            self.message = String::from("The AI is thinking, press m to make it move now");
+            return;
+        }
+        let team = self.amazons.to_move();
+        let pos = self.cursor;
+        let own_piece = self.amazons.team_pieces(team).any(|p| p == pos);
+        self.message.clear();
+
+        match self.stage {
+            Stage::Piece | Stage::Destination(_) if own_piece => {
+                self.stage = Stage::Destination(pos);
+                if self.choices().is_empty() {
+                    self.stage = Stage::Piece;
+                    self.message = String::from("That piece can't move");
+                }
+            },
+            Stage::Piece => {
+                self.message = String::from("Pick one of your pieces");
+            },
+            Stage::Destination(from) => {
+                if self.choices().contains(&pos) {
+                    self.stage = Stage::Shot(from, pos);
+                } else {
+                    self.message = String::from("The piece can't reach there");
+                }
+            },
+            Stage::Shot(from, to) => {
+                if !self.choices().contains(&pos) {
+                    self.message = String::from("Can't shoot there");
+                } else if let Err(msg) = self.amazons.player_move(team, from, to, pos) {
+                    self.message = format!("Invalid Move: {}", msg);
+                } else {
+                    self.stage = Stage::Piece;
+                }
+            },
+        }
+    }
+
+    /// Start a search when an AI player is to move, and play its move
+    /// once it is done. Returns whether there is anything new to show.
+    fn step_ai(&mut self) -> bool {
+        if let Some((team, handle)) = &self.thinking {
+            let mut changed = false;
+            for info in handle.progress() {
+                if let Some(mv) = info.pv.first() {
+                    self.message = format!("{:?} is thinking: depth {}, best {} ({:+})",
+                                           team, info.depth, mv, info.score);
+                    changed = true;
+                }
+            }
+            if self.game_over() {
+                // Out of time while thinking.
+                self.cancel_search();
+                return true;
+            }
+            if !handle.is_finished() {
+                return changed;
+            }
+            let (_, handle) = self.thinking.take().unwrap();
+            if let Some(info) = self.amazons.finish_search(handle) {
+                self.predicted = info.pv.get(1).cloned();
+            }
+            self.message.clear();
+            self.stage = Stage::Piece;
+            return true;
+        }
+
+        // Ponder until the position changes.
+        let here = (self.amazons.ply(), self.amazons.last_move());
+        if self.game_over() || self.pondering.as_ref().is_some_and(|(at, _)| *at != here) {
+            self.stop_pondering();
+        }
+        if self.game_over() {
+            return false;
+        }
+        let team = self.amazons.to_move();
+        let strategy = match self.players[&team] {
+            Player::Ai(s) => s,
+            Player::Human => {
+                let idle = self.pondering.is_none() && self.hinting.is_none();
+                if let (true, true, Player::Ai(s)) = (self.ponder, idle, self.players[&team.other()]) {
+                    let handle = self.amazons.start_ponder(team.other(), s, self.predicted.take());
+                    self.pondering = Some((here, handle));
+                }
+                return false;
+            },
+        };
+        self.thinking = Some((team, self.amazons.start_search(team, strategy)));
+        self.message = format!("{:?} is thinking...", team);
+        true
+    }
+
+    /// Abandon the running search without playing its move.
+    fn cancel_search(&mut self) {
+        if let Some((_, handle)) = self.thinking.take() {
+            handle.cancel();
+        }
+    }
+
+    fn stop_pondering(&mut self) {
+        if let Some((_, handle)) = self.pondering.take() {
+            handle.cancel();
+        }
+    }
+
+    /// Show the hints once their search is done, and drop them if the
+    /// position changed meanwhile. Returns whether there is anything
+    /// new to show.
+    fn step_hints(&mut self) -> bool {
+        let here = (self.amazons.ply(), self.amazons.last_move());
+        match &self.hinting {
+            Some((at, _)) if *at != here || self.game_over() => self.stop_hints(),
+            Some((_, handle)) if handle.is_finished() => {
+                let (at, handle) = self.hinting.take().unwrap();
+                self.hints = handle.join();
+                self.hints_for = at;
+                self.message.clear();
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    /// Abandon the running search for hints.
+    fn stop_hints(&mut self) {
+        if let Some((_, handle)) = self.hinting.take() {
+            handle.stop();
+            handle.join();
+        }
+    }
+
+    fn draw<W: Write>(&mut self, screen: &mut W) -> io::Result<()> {
+        write!(screen, "{}{}", clear::All, cursor::Goto(LEFT, TOP - 1))?;
+        write!(screen, "Amazons")?;
+
+        if self.hints_for != (self.amazons.ply(), self.amazons.last_move()) {
+            self.hints.clear();
+        }
+        let choices = self.choices();
+        let (cursor, stage) = (self.cursor, self.stage);
+        let last = self.amazons.last_move();
+        let territory = if self.show_territory {
+            Some(self.amazons.territory(EvalStrategy::QueenDistance))
+        } else {
+            None
+        };
+        let highlight = |p: Pos| {
+            let selected = match stage {
+                Stage::Piece => false,
+                Stage::Destination(from) => p == from,
+                Stage::Shot(from, to) => p == from || p == to,
+            };
+            if p == cursor {
+                Some(Highlight::Cursor)
+            } else if selected {
+                Some(Highlight::Selected)
+            } else if choices.contains(&p) {
+                match stage {
+                    Stage::Shot(..) => Some(Highlight::Target),
+                    _ => Some(Highlight::Reachable),
+                }
+            } else {
+                hint_highlight(&self.hints, p)
+                    .or_else(|| last_move_highlight(&last, p))
+                    .or_else(|| territory.as_ref().and_then(|t| t.owner(p)).map(Highlight::Territory))
+            }
+        };
+        let lines = board_lines(self.amazons.curr_board(), &highlight, self.style);
+        for (i, line) in lines.iter().enumerate() {
+            write!(screen, "{}{}", cursor::Goto(LEFT, TOP + i as u16), line)?;
+        }
+        write!(screen, "{}{}", color::Fg(color::Reset), color::Bg(color::Reset))?;
+
+        let mut panel = Vec::new();
+        for t in Team::teams() {
+            panel.push(format!("{:?}: {:?}", t, self.players[&t]));
+        }
+        let team = self.amazons.to_move();
+        if let Some(clock) = self.amazons.clock() {
+            panel.push(clock_summary(clock));
+        }
+        if let Some(winner) = self.amazons.winner() {
+            panel.push(format!("{:?} wins!", winner));
+        } else {
+            panel.push(format!("{:?} to move", team));
+        }
+        panel.push(format!("Evaluation for Red: {}",
+                           self.amazons.evaluate(0, Team::Red, EvalStrategy::QueenDistance)));
+        if let Some(t) = &territory {
+            panel.push(territory_summary(t));
+            panel.push(regions_summary(self.amazons.curr_board()));
+        }
+        panel.push(self.message.clone());
+        for (i, hint) in self.hints.iter().enumerate() {
+            panel.push(hint_summary(i + 1, hint));
+        }
+        panel.push(String::new());
+
+        panel.push(String::from("Moves"));
+        let moves = self.amazons.moves();
+        let shown = moves.len().saturating_sub(10);
+        for (i, mv) in moves.iter().enumerate().skip(shown) {
+            panel.push(format!("{:3}. {:?} {}", i + 1, mv.player.team, mv));
+        }
+        panel.push(String::new());
+        panel.extend(HELP.iter().map(|s| s.to_string()));
+
+        let panel_left = LEFT + LABEL_WIDTH + 2 * self.size() as u16;
+        for (i, line) in panel.iter().enumerate() {
+            write!(screen, "{}{}", cursor::Goto(panel_left, TOP + i as u16), line)?;
+        }
+        screen.flush()
+    }
+}